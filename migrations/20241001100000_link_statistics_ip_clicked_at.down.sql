@@ -0,0 +1,6 @@
+-- Add down migration script here
+drop index if exists idx_link_statistics_link_id_clicked_at;
+
+alter table link_statistics
+    drop column if exists ip_address,
+    drop column if exists clicked_at;
@@ -0,0 +1,6 @@
+-- Add up migration script here
+alter table link_statistics
+    add column if not exists ip_address inet,
+    add column if not exists clicked_at timestamptz not null default now();
+
+create index if not exists idx_link_statistics_link_id_clicked_at on link_statistics using btree (link_id, clicked_at);
@@ -0,0 +1,2 @@
+-- Add up migration script here
+create unique index if not exists idx_links_lower_id on links using btree (lower(id));
@@ -0,0 +1,14 @@
+-- Add up migration script here
+create table IF NOT EXISTS idempotency_keys
+(
+    key text not null primary key,
+    request_hash text not null,
+    link_id text not null,
+    created_at timestamptz not null default now(),
+    constraint fk_links
+        foreign key (link_id)
+            references links (id)
+            on delete cascade
+);
+
+create index idx_idempotency_keys_created_at on idempotency_keys using btree (created_at);
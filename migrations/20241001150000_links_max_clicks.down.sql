@@ -0,0 +1,3 @@
+-- Add down migration script here
+alter table links drop column if exists limited_clicks;
+alter table links drop column if exists max_clicks;
@@ -0,0 +1,3 @@
+-- Add up migration script here
+alter table links add column if not exists max_clicks bigint;
+alter table links add column if not exists limited_clicks bigint not null default 0;
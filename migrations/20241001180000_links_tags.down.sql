@@ -0,0 +1,3 @@
+-- Add down migration script here
+drop index if exists idx_links_tags;
+alter table links drop column if exists tags;
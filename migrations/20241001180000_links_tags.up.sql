@@ -0,0 +1,4 @@
+-- Add up migration script here
+alter table links add column if not exists tags text[] not null default '{}';
+
+create index if not exists idx_links_tags on links using gin (tags);
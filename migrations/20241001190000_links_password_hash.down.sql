@@ -0,0 +1,2 @@
+-- Add down migration script here
+alter table links drop column if exists password_hash;
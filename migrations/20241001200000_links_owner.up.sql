@@ -0,0 +1,4 @@
+-- Add up migration script here
+alter table links add column if not exists owner text;
+
+create index if not exists idx_links_owner on links using btree (owner);
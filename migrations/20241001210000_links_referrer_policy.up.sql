@@ -0,0 +1,2 @@
+-- Add up migration script here
+alter table links add column if not exists referrer_policy text;
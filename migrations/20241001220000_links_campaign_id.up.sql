@@ -0,0 +1,4 @@
+-- Add up migration script here
+alter table links add column if not exists campaign_id text;
+
+create index if not exists idx_links_campaign_id on links using btree (campaign_id);
@@ -0,0 +1,2 @@
+-- Add up migration script here
+create index if not exists idx_links_target_url on links using hash (target_url);
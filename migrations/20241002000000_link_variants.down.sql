@@ -0,0 +1,3 @@
+-- Add down migration script here
+alter table link_statistics drop column if exists variant_url;
+drop table if exists link_variants;
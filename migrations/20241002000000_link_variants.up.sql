@@ -0,0 +1,10 @@
+-- Add up migration script here
+create table if not exists link_variants
+(
+    link_id text not null references links (id) on delete cascade,
+    target_url text not null,
+    weight integer not null check (weight > 0),
+    primary key (link_id, target_url)
+);
+
+alter table link_statistics add column if not exists variant_url text;
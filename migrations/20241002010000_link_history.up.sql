@@ -0,0 +1,11 @@
+-- Add up migration script here
+create table if not exists link_history
+(
+    id bigserial primary key,
+    link_id text not null references links (id) on delete cascade,
+    old_target text not null,
+    new_target text not null,
+    changed_at timestamptz not null default now()
+);
+
+create index if not exists idx_link_history_link_id_changed_at on link_history (link_id, changed_at);
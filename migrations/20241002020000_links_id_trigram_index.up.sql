@@ -0,0 +1,4 @@
+-- Add up migration script here
+create extension if not exists pg_trgm;
+
+create index if not exists idx_links_id_trgm on links using gin (id gin_trgm_ops);
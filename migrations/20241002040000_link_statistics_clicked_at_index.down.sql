@@ -0,0 +1,2 @@
+-- Add down migration script here
+drop index if exists idx_link_statistics_clicked_at;
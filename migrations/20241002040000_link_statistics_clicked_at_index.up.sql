@@ -0,0 +1,2 @@
+-- Add up migration script here
+create index if not exists idx_link_statistics_clicked_at on link_statistics (clicked_at);
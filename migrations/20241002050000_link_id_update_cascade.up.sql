@@ -0,0 +1,19 @@
+-- Add up migration script here
+alter table link_statistics
+    drop constraint fk_links,
+    add constraint fk_links foreign key (link_id) references links (id) on update cascade;
+
+alter table idempotency_keys
+    drop constraint fk_links,
+    add constraint fk_links foreign key (link_id) references links (id)
+        on update cascade on delete cascade;
+
+alter table link_variants
+    drop constraint link_variants_link_id_fkey,
+    add constraint link_variants_link_id_fkey foreign key (link_id) references links (id)
+        on update cascade on delete cascade;
+
+alter table link_history
+    drop constraint link_history_link_id_fkey,
+    add constraint link_history_link_id_fkey foreign key (link_id) references links (id)
+        on update cascade on delete cascade;
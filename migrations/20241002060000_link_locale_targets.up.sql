@@ -0,0 +1,8 @@
+-- Add up migration script here
+create table if not exists link_locale_targets
+(
+    link_id text not null references links (id) on update cascade on delete cascade,
+    lang text not null,
+    target_url text not null,
+    primary key (link_id, lang)
+);
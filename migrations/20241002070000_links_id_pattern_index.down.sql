@@ -0,0 +1,2 @@
+-- Add down migration script here
+drop index if exists idx_links_id_pattern;
@@ -0,0 +1,2 @@
+-- Add up migration script here
+create index if not exists idx_links_id_pattern on links using btree (id text_pattern_ops);
@@ -0,0 +1,4 @@
+-- Add down migration script here
+drop index if exists idx_links_lower_id;
+
+create unique index if not exists idx_links_lower_id on links using btree (lower(id));
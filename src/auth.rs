@@ -1,9 +1,13 @@
+use std::sync::Arc;
+
 use axum::{extract::{Request, State}, http::StatusCode, middleware::Next, response::IntoResponse};
 use metrics::counter;
 use sqlx::PgPool;
 use sha3::{Sha3_256, Digest};
 
-use crate::utils::internal_error;
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::utils::{database_error, internal_error, timed};
 
 struct Setting {
     #[allow(dead_code)]
@@ -11,26 +15,50 @@ struct Setting {
     encrypted_global_api_key: String
 }
 
+/// Identifies the API key a request was authenticated with, by its sha3 hash
+/// so the key itself is never stored. Set by `auth` for the handlers behind it.
+#[derive(Clone)]
+pub struct Owner(pub String);
+
 pub async fn auth (
     State(pool): State<PgPool>,
-    req: Request,
+    State(config): State<Arc<Config>>,
+    mut req: Request,
     next: Next
-) -> Result<impl IntoResponse, (StatusCode, String)> {
+) -> Result<impl IntoResponse, ApiError> {
     let labels = [("uri", format!("{}", req.uri()))];
 
-    let api_key = req
+    let bearer_token = req
         .headers()
-        .get("x-api-key")
-        .map(|value| value.to_str().unwrap_or_default())
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    let api_key = bearer_token
+        .or_else(|| req
+            .headers()
+            .get("x-api-key")
+            .map(|value| value.to_str().unwrap_or_default())
+        )
         .ok_or_else(|| {
             tracing::error!("Unauthorized call to API");
             counter!("unauthenticated_calls_count", &labels).increment(1);
-            (StatusCode::UNAUTHORIZED, "Unauthorized".into())
+            ApiError::new(StatusCode::UNAUTHORIZED, "Unauthorized")
         })?;
+
+    let mut hasher = Sha3_256::new();
+    hasher.update(api_key.as_bytes());
+    let owner = Owner(format!("{:x}", hasher.finalize()));
+
+    if config.api_keys.contains(api_key) {
+        req.extensions_mut().insert(owner);
+
+        return Ok(next.run(req).await);
+    }
     
-    let fetch_setting_timeout = tokio::time::Duration::from_millis(300);
+    let fetch_setting_timeout = config.db_timeout();
 
-    let setting = tokio::time::timeout(
+    let setting = timed("select_settings", tokio::time::timeout(
         fetch_setting_timeout,
         sqlx::query_as!(
             Setting,
@@ -38,21 +66,19 @@ pub async fn auth (
             "DEFAULT_SETTINGS"
         )
         .fetch_one(&pool)
-    )
+    ))
     .await
     .map_err(internal_error)?
-    .map_err(internal_error)?;
+    .map_err(database_error)?;
 
-    let mut hasher = Sha3_256::new();
-    hasher.update(api_key.as_bytes());
-    let provided_api_key = hasher.finalize();
-
-    if setting.encrypted_global_api_key != format!("{provided_api_key:x}") {
+    if setting.encrypted_global_api_key != owner.0 {
         tracing::error!("Unaithorized call to API: Incorrect key supplied");
         counter!("unauthenticated_calls_count", &labels).increment(1);
 
-        return Err((StatusCode::UNAUTHORIZED, "Unauthorized".into()));
+        return Err(ApiError::new(StatusCode::UNAUTHORIZED, "Unauthorized"));
     }
+
+    req.extensions_mut().insert(owner);
     
     Ok(next.run(req).await)
 }
\ No newline at end of file
@@ -0,0 +1,47 @@
+use moka::future::Cache;
+use tokio::time::Duration;
+
+use crate::config::Config;
+use crate::routes::RedirectLink;
+
+/// Links resolved by `redirect`, keyed by id. Writers must call `invalidate`
+/// whenever a link changes so stale targets don't keep being served.
+#[derive(Clone)]
+pub struct LinkCache {
+    links: Cache<String, RedirectLink>,
+    case_insensitive_ids: bool
+}
+
+impl LinkCache {
+    pub fn new(config: &Config) -> Self {
+        let links = Cache::builder()
+            .max_capacity(config.link_cache_capacity)
+            .time_to_live(Duration::from_secs(config.link_cache_ttl_seconds))
+            .build();
+
+        Self {
+            links,
+            case_insensitive_ids: config.case_insensitive_ids
+        }
+    }
+
+    pub async fn get(&self, link_id: &str) -> Option<RedirectLink> {
+        self.links.get(&self.key(link_id)).await
+    }
+
+    pub async fn insert(&self, link_id: &str, link: RedirectLink) {
+        self.links.insert(self.key(link_id), link).await;
+    }
+
+    pub async fn invalidate(&self, link_id: &str) {
+        self.links.invalidate(&self.key(link_id)).await;
+    }
+
+    fn key(&self, link_id: &str) -> String {
+        if self.case_insensitive_ids {
+            link_id.to_lowercase()
+        } else {
+            link_id.to_string()
+        }
+    }
+}
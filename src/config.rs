@@ -0,0 +1,582 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use axum::http::{HeaderValue, StatusCode};
+use tokio::time::Duration;
+use tracing::Level;
+use url::{Host, Url};
+
+use crate::utils::{is_referrer_policy, normalize_target_url, DomainPattern, REFERRER_POLICIES};
+
+const DEFAULT_DB_TIMEOUT_MS: u64 = 300;
+const DEFAULT_DB_MAX_CONNECTIONS: u32 = 20;
+const DEFAULT_DB_ACQUIRE_TIMEOUT_MS: u64 = 200;
+const DEFAULT_DB_IDLE_TIMEOUT_SECONDS: u64 = 10 * 60;
+const DEFAULT_DB_READ_RETRIES: u32 = 2;
+const DEFAULT_CACHE_CONTROL_HEADER_VALUE: &str =
+    "public, max-age=300, s-maxage=300, stale-while-revalidate=300, stale-if-error=300";
+const DEFAULT_REDIRECT_STATUS: u16 = 307;
+const DEFAULT_PERMANENT_REDIRECT_STATUS: u16 = 301;
+const DEFAULT_STATISTICS_BATCH_SIZE: usize = 100;
+const DEFAULT_STATISTICS_FLUSH_INTERVAL_MS: u64 = 500;
+const DEFAULT_RATE_LIMIT_PER_MINUTE: u32 = 10;
+const DEFAULT_BASE_URL: &str = "http://localhost:3000";
+const DEFAULT_LINK_CACHE_CAPACITY: u64 = 10_000;
+const DEFAULT_LINK_CACHE_TTL_SECONDS: u64 = 300;
+const DEFAULT_BULK_CREATE_MAX_LINKS: usize = 1000;
+const DEFAULT_BULK_DB_TIMEOUT_MS: u64 = 5000;
+const DEFAULT_DELETE_LINKS_DB_TIMEOUT_MS: u64 = 10000;
+const DEFAULT_SHUTDOWN_GRACE_PERIOD_SECONDS: u64 = 30;
+const DEFAULT_VERIFY_TARGET_TIMEOUT_MS: u64 = 2000;
+const DEFAULT_VERIFY_TARGET_MAX_REDIRECTS: usize = 3;
+const DEFAULT_METADATA_FETCH_TIMEOUT_MS: u64 = 3000;
+const DEFAULT_METADATA_MAX_BYTES: usize = 256 * 1024;
+const DEFAULT_METADATA_CACHE_TTL_SECONDS: u64 = 60 * 60;
+const DEFAULT_ID_LENGTH: usize = 11;
+const DEFAULT_ID_ALPHABET: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+const DEFAULT_BODY_LIMIT_BYTES: usize = 8 * 1024;
+const DEFAULT_BULK_BODY_LIMIT_BYTES: usize = 2 * 1024 * 1024;
+const DEFAULT_MAX_TARGET_URL_LENGTH: usize = 2048;
+const DEFAULT_TOP_LINKS_MAX_LIMIT: i64 = 100;
+const DEFAULT_TOP_REFERERS_MAX_LIMIT: i64 = 100;
+const DEFAULT_NOT_FOUND_LOG_SAMPLE_RATE: f64 = 0.01;
+const DEFAULT_LINK_HISTORY_MAX_ENTRIES: i64 = 100;
+const DEFAULT_SIMILAR_ID_THRESHOLD: f64 = 0.3;
+const DEFAULT_STATISTICS_RETENTION_INTERVAL_SECONDS: u64 = 60 * 60;
+const DEFAULT_STATISTICS_RETENTION_BATCH_SIZE: i64 = 10_000;
+const DEFAULT_IDEMPOTENCY_KEY_TTL_SECONDS: u64 = 24 * 60 * 60;
+/// Top level routes a custom id would otherwise shadow.
+const ROUTE_RESERVED_IDS: [&str; 9] = [
+    "api-docs", "campaigns", "create", "health", "links", "metrics", "ready", "statistics", "status"
+];
+
+/// Backend the statistics writer stores clicks in, see `statistics::connect_sink`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum StatisticsSinkKind {
+    Postgres,
+    Redis
+}
+
+impl FromStr for StatisticsSinkKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "postgres" => Ok(StatisticsSinkKind::Postgres),
+            "redis" => Ok(StatisticsSinkKind::Redis),
+            other => Err(format!("unknown statistics sink: {other}"))
+        }
+    }
+}
+
+/// Per-deployment settings, read once from the environment at startup.
+pub struct Config {
+    pub db_timeout_ms: u64,
+    /// Upper bound on pooled connections. Keep the sum over all instances
+    /// below the server's `max_connections`.
+    pub db_max_connections: u32,
+    /// How long a request waits for a pooled connection before answering 503.
+    /// Keep it below `db_timeout_ms`, otherwise the query timeout fires first
+    /// and a saturated pool shows up as a 500.
+    pub db_acquire_timeout_ms: u64,
+    /// Idle connections above the minimum are closed after this long.
+    pub db_idle_timeout_seconds: u64,
+    /// How often the redirect and statistics reads are retried after a
+    /// connection error. Writes are never retried.
+    pub db_read_retries: u32,
+    /// Leave the schema alone at startup, for deployments migrating it themselves.
+    pub skip_migrations: bool,
+    pub cache_control_header: String,
+    /// Status used when redirecting links that are not marked permanent, one
+    /// of 301, 302, 303, 307 or 308, the statuses clients follow with `Location`.
+    pub redirect_status: StatusCode,
+    /// Status used for permanent links, 301 or the method preserving 308.
+    pub permanent_redirect_status: StatusCode,
+    pub statistics_batch_size: usize,
+    pub statistics_flush_interval_ms: u64,
+    /// Clicks older than this many days are deleted, they are kept forever when unset.
+    pub statistics_retention_days: Option<u64>,
+    /// How often the retention job looks for old clicks.
+    pub statistics_retention_interval_seconds: u64,
+    /// Rows deleted per statement by the retention job.
+    pub statistics_retention_batch_size: i64,
+    /// Requests per minute allowed per client on the create and update routes.
+    pub rate_limit_per_minute: u32,
+    /// Only enable when running behind a proxy that sets `X-Forwarded-For`,
+    /// otherwise clients can pick their own ip.
+    pub trust_forwarded_for: bool,
+    /// Keys accepted by the auth middleware in addition to the global key in `settings`.
+    pub api_keys: HashSet<String>,
+    /// Host this service is reachable under; targets pointing at it are rejected.
+    /// Stored in punycode like the hosts of normalized targets.
+    pub base_host: Option<String>,
+    /// Public url short links are served under, e.g. `https://short.ly`.
+    pub base_url: Url,
+    /// Resolve redirects regardless of the casing of the requested id, and
+    /// refuse to create a link whose id only differs in case from an existing one.
+    pub case_insensitive_ids: bool,
+    /// When set, `/metrics` is served on this address instead of the main listener.
+    pub metrics_addr: Option<SocketAddr>,
+    /// Maximum number of links kept in the redirect cache.
+    pub link_cache_capacity: u64,
+    /// Upper bound on how long a cached link is served, which also bounds how
+    /// stale another instance's cache can get after an update.
+    pub link_cache_ttl_seconds: u64,
+    /// Maximum number of links accepted by a single bulk create request.
+    pub bulk_create_max_links: usize,
+    /// Timeout of the insert of a bulk create request, which writes up to
+    /// `bulk_create_max_links` rows where `db_timeout_ms` is sized for one.
+    pub bulk_db_timeout_ms: u64,
+    /// Timeout of deleting links by tag or prefix, which removes every
+    /// matching link and its statistics in one transaction.
+    pub delete_links_db_timeout_ms: u64,
+    /// How long in-flight requests may keep running after a shutdown signal.
+    pub shutdown_grace_period_seconds: u64,
+    /// Characters per generated link id, each drawn uniformly from `id_alphabet`.
+    pub id_length: usize,
+    /// Characters generated link ids are made of, alphanumerics by default.
+    pub id_alphabet: Vec<char>,
+    /// Skip recording clicks for requests carrying `DNT: 1`.
+    pub honor_do_not_track: bool,
+    /// Largest request body accepted by the single link write routes.
+    pub body_limit_bytes: usize,
+    /// Largest request body accepted by the bulk create route.
+    pub bulk_body_limit_bytes: usize,
+    pub max_target_url_length: usize,
+    /// Largest `limit` accepted by `GET /links/top`.
+    pub top_links_max_limit: i64,
+    /// Largest `limit` accepted by `GET /statistics/referers/top`.
+    pub top_referers_max_limit: i64,
+    /// `/` redirects here instead of answering with the service name and version.
+    pub homepage_url: Option<String>,
+    /// Unknown ids are redirected here instead of getting a 404.
+    pub not_found_redirect: Option<String>,
+    /// Share of redirect misses logged as a warning with the requested id,
+    /// from 0 for none to 1 for all. Every miss is counted either way.
+    pub not_found_log_sample_rate: f64,
+    /// Add up to 3 ids resembling the requested one to the 404 of a redirect
+    /// miss. Costs a trigram query on every miss, so it is off by default.
+    pub suggest_similar_ids: bool,
+    /// Trigram similarity from 0 to 1 a suggested id needs at least.
+    pub similar_id_threshold: f64,
+    /// How long an `Idempotency-Key` keeps returning the link it created.
+    pub idempotency_key_ttl_seconds: u64,
+    pub statistics_sink: StatisticsSinkKind,
+    /// Count every redirect in the link's `click_count`, an update per
+    /// redirect that gives exact totals without reading the statistics.
+    /// Counted regardless of `DNT` and `notrack`, as nothing about the client
+    /// is kept.
+    pub count_clicks: bool,
+    /// Keep a row per click with referer, user agent and ip, which the
+    /// statistics routes aggregate. Independent of `count_clicks`.
+    pub record_statistics: bool,
+    /// Read the link and insert its click in one transaction before answering
+    /// the redirect, instead of queueing the click for the batched writer.
+    /// Clicks are no longer lost when the process dies with a full buffer, but
+    /// every redirect skips the link cache, holds a connection throughout and
+    /// waits for the insert and commit, adding database round trips to its
+    /// latency. A failed insert still redirects and is counted in
+    /// `statistics_insert_failures_count`. Needs the postgres statistics sink.
+    pub synchronous_statistics: bool,
+    /// Carry the query of the short link request, e.g. `utm_source`, over to
+    /// the target of every link, not just those with `forward_path`.
+    pub preserve_query: bool,
+    /// `Referrer-Policy` sent with redirects so targets don't see the short
+    /// url as referrer, unless the link sets its own. Not sent when unset.
+    pub referrer_policy: Option<String>,
+    /// Let `?status=` pick the redirect status of a single request, for
+    /// testing. Off by default since a 301 or 308 gets cached by browsers.
+    pub allow_redirect_status_override: bool,
+    /// Accept path-only targets like `/dashboard`, redirected to relative to `base_url`.
+    pub allow_relative_targets: bool,
+    /// Send a `HEAD` request to the target of every new link and reject it
+    /// when it answers with an error or not at all. Adds that request's
+    /// latency to `create_link`.
+    pub verify_targets: bool,
+    /// Compress response bodies with gzip or brotli for clients that accept it.
+    pub compress_responses: bool,
+    pub verify_target_timeout_ms: u64,
+    /// Redirects followed while verifying a target before giving up on it.
+    pub verify_target_max_redirects: usize,
+    /// When not empty, only targets on a matching host can be shortened.
+    pub allowed_target_domains: Vec<DomainPattern>,
+    /// Targets on a matching host are rejected, even when also allowed.
+    pub blocked_target_domains: Vec<DomainPattern>,
+    /// Target changes kept per link in `link_history`, older ones are dropped.
+    pub link_history_max_entries: i64,
+    /// Furthest ahead a link's `expiresAt` may be set, unbounded when unset.
+    pub max_expiry_days: Option<i64>,
+    /// Reject new links without an `expiresAt`.
+    pub require_expiry: bool,
+    /// How long `GET /links/{id}/metadata` waits for the target page.
+    pub metadata_fetch_timeout_ms: u64,
+    /// Bytes of the target page read for Open Graph tags, the rest is skipped.
+    pub metadata_max_bytes: usize,
+    /// How long fetched Open Graph tags are served before the target is read again.
+    pub metadata_cache_ttl_seconds: u64,
+    /// Lowercased ids custom links may not use, the route names plus `RESERVED_IDS`.
+    pub reserved_ids: HashSet<String>,
+    /// OTLP collector request spans are exported to, tracing is off when unset.
+    pub otlp_endpoint: Option<String>,
+    /// Send a `Server-Timing` header with the database time of each request.
+    /// Off by default, as it tells clients how long lookups take.
+    pub server_timing: bool,
+    /// Level the per-request access log is written at.
+    pub access_log_level: Level,
+    pub redis_url: Option<String>
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let redirect_status = StatusCode::from_u16(env_or("REDIRECT_STATUS", DEFAULT_REDIRECT_STATUS))
+            .ok()
+            .filter(|status| {
+                matches!(
+                    *status,
+                    StatusCode::MOVED_PERMANENTLY
+                        | StatusCode::FOUND
+                        | StatusCode::SEE_OTHER
+                        | StatusCode::TEMPORARY_REDIRECT
+                        | StatusCode::PERMANENT_REDIRECT
+                )
+            })
+            .expect("REDIRECT_STATUS must be 301, 302, 303, 307 or 308");
+
+        let permanent_redirect_status = StatusCode::from_u16(env_or(
+            "PERMANENT_REDIRECT_STATUS",
+            DEFAULT_PERMANENT_REDIRECT_STATUS
+        ))
+        .ok()
+        .filter(|status| {
+            matches!(*status, StatusCode::MOVED_PERMANENTLY | StatusCode::PERMANENT_REDIRECT)
+        })
+        .expect("PERMANENT_REDIRECT_STATUS must be 301 or 308");
+
+        let cache_control_header = env_or(
+            "CACHE_CONTROL_HEADER",
+            DEFAULT_CACHE_CONTROL_HEADER_VALUE.to_string()
+        );
+        assert!(
+            HeaderValue::from_str(&cache_control_header).is_ok(),
+            "CACHE_CONTROL_HEADER must be a valid header value"
+        );
+
+        let db_max_connections = env_or("DB_MAX_CONNECTIONS", DEFAULT_DB_MAX_CONNECTIONS);
+        assert!(db_max_connections > 0, "DB_MAX_CONNECTIONS must be greater than 0");
+
+        let statistics_batch_size = env_or("STATISTICS_BATCH_SIZE", DEFAULT_STATISTICS_BATCH_SIZE);
+        assert!(statistics_batch_size > 0, "STATISTICS_BATCH_SIZE must be greater than 0");
+
+        let statistics_flush_interval_ms =
+            env_or("STATISTICS_FLUSH_INTERVAL_MS", DEFAULT_STATISTICS_FLUSH_INTERVAL_MS);
+        assert!(statistics_flush_interval_ms > 0, "STATISTICS_FLUSH_INTERVAL_MS must be greater than 0");
+
+        let statistics_retention_days = std::env::var("STATISTICS_RETENTION_DAYS")
+            .ok()
+            .filter(|days| !days.is_empty())
+            .map(|days| {
+                days.parse::<u64>().expect("STATISTICS_RETENTION_DAYS must be a number of days")
+            });
+        assert!(
+            statistics_retention_days.is_none_or(|days| days > 0),
+            "STATISTICS_RETENTION_DAYS must be greater than 0"
+        );
+
+        let statistics_retention_interval_seconds = env_or(
+            "STATISTICS_RETENTION_INTERVAL_SECONDS",
+            DEFAULT_STATISTICS_RETENTION_INTERVAL_SECONDS
+        );
+        assert!(
+            statistics_retention_interval_seconds > 0,
+            "STATISTICS_RETENTION_INTERVAL_SECONDS must be greater than 0"
+        );
+
+        let statistics_retention_batch_size =
+            env_or("STATISTICS_RETENTION_BATCH_SIZE", DEFAULT_STATISTICS_RETENTION_BATCH_SIZE);
+        assert!(
+            statistics_retention_batch_size > 0,
+            "STATISTICS_RETENTION_BATCH_SIZE must be greater than 0"
+        );
+
+        let rate_limit_per_minute = env_or("RATE_LIMIT_PER_MINUTE", DEFAULT_RATE_LIMIT_PER_MINUTE);
+        assert!(rate_limit_per_minute > 0, "RATE_LIMIT_PER_MINUTE must be greater than 0");
+
+        let id_length = env_or("ID_LENGTH", DEFAULT_ID_LENGTH);
+        assert!(id_length > 0, "ID_LENGTH must be greater than 0");
+
+        let id_alphabet: Vec<char> = env_or("ID_ALPHABET", DEFAULT_ID_ALPHABET.to_string())
+            .chars()
+            .collect();
+        assert!(id_alphabet.len() > 1, "ID_ALPHABET must have at least 2 characters");
+        assert!(
+            id_alphabet.iter().all(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_'),
+            "ID_ALPHABET may only contain alphanumerics, '-' and '_'"
+        );
+        assert!(
+            id_alphabet.iter().collect::<HashSet<_>>().len() == id_alphabet.len(),
+            "ID_ALPHABET must not contain a character twice"
+        );
+
+        let top_links_max_limit = env_or("TOP_LINKS_MAX_LIMIT", DEFAULT_TOP_LINKS_MAX_LIMIT);
+        assert!(top_links_max_limit > 0, "TOP_LINKS_MAX_LIMIT must be greater than 0");
+
+        let top_referers_max_limit = env_or("TOP_REFERERS_MAX_LIMIT", DEFAULT_TOP_REFERERS_MAX_LIMIT);
+        assert!(top_referers_max_limit > 0, "TOP_REFERERS_MAX_LIMIT must be greater than 0");
+
+        let base_url = Url::parse(&env_or("BASE_URL", DEFAULT_BASE_URL.to_string()))
+            .expect("BASE_URL must be a valid url");
+
+        let statistics_sink = env_or("STATISTICS_SINK", StatisticsSinkKind::Postgres);
+        let redis_url = std::env::var("REDIS_URL").ok().filter(|url| !url.is_empty());
+        assert!(
+            statistics_sink != StatisticsSinkKind::Redis || redis_url.is_some(),
+            "REDIS_URL is required when STATISTICS_SINK is redis"
+        );
+
+        let synchronous_statistics = env_or("SYNCHRONOUS_STATISTICS", false);
+        assert!(
+            !synchronous_statistics || statistics_sink == StatisticsSinkKind::Postgres,
+            "SYNCHRONOUS_STATISTICS requires STATISTICS_SINK to be postgres"
+        );
+
+        let not_found_redirect = redirect_url("NOT_FOUND_REDIRECT");
+        let homepage_url = redirect_url("HOMEPAGE_URL");
+
+        let not_found_log_sample_rate =
+            env_or("NOT_FOUND_LOG_SAMPLE_RATE", DEFAULT_NOT_FOUND_LOG_SAMPLE_RATE);
+        assert!(
+            (0.0..=1.0).contains(&not_found_log_sample_rate),
+            "NOT_FOUND_LOG_SAMPLE_RATE must be between 0 and 1"
+        );
+
+        let similar_id_threshold = env_or("SIMILAR_ID_THRESHOLD", DEFAULT_SIMILAR_ID_THRESHOLD);
+        assert!(
+            (0.0..=1.0).contains(&similar_id_threshold),
+            "SIMILAR_ID_THRESHOLD must be between 0 and 1"
+        );
+
+        let referrer_policy = std::env::var("REFERRER_POLICY")
+            .ok()
+            .filter(|policy| !policy.is_empty());
+        assert!(
+            referrer_policy.as_deref().is_none_or(is_referrer_policy),
+            "REFERRER_POLICY must be one of {}",
+            REFERRER_POLICIES.join(", ")
+        );
+
+        let link_history_max_entries =
+            env_or("LINK_HISTORY_MAX_ENTRIES", DEFAULT_LINK_HISTORY_MAX_ENTRIES);
+        assert!(link_history_max_entries > 0, "LINK_HISTORY_MAX_ENTRIES must be greater than 0");
+
+        let max_expiry_days = std::env::var("MAX_EXPIRY_DAYS")
+            .ok()
+            .filter(|days| !days.is_empty())
+            .map(|days| days.parse::<i64>().expect("MAX_EXPIRY_DAYS must be a number of days"));
+        assert!(
+            max_expiry_days.is_none_or(|days| days > 0),
+            "MAX_EXPIRY_DAYS must be greater than 0"
+        );
+
+        let reserved_ids = ROUTE_RESERVED_IDS
+            .into_iter()
+            .map(str::to_string)
+            .chain(
+                env_or("RESERVED_IDS", String::new())
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|id| !id.is_empty())
+                    .map(str::to_lowercase)
+            )
+            .collect();
+
+        Self {
+            db_timeout_ms: env_or("DB_TIMEOUT_MS", DEFAULT_DB_TIMEOUT_MS),
+            db_max_connections,
+            db_acquire_timeout_ms: env_or("DB_ACQUIRE_TIMEOUT_MS", DEFAULT_DB_ACQUIRE_TIMEOUT_MS),
+            db_idle_timeout_seconds: env_or("DB_IDLE_TIMEOUT_SECONDS", DEFAULT_DB_IDLE_TIMEOUT_SECONDS),
+            db_read_retries: env_or("DB_READ_RETRIES", DEFAULT_DB_READ_RETRIES),
+            skip_migrations: env_or("SKIP_MIGRATIONS", false),
+            cache_control_header,
+            redirect_status,
+            permanent_redirect_status,
+            statistics_batch_size,
+            statistics_flush_interval_ms,
+            statistics_retention_days,
+            statistics_retention_interval_seconds,
+            statistics_retention_batch_size,
+            rate_limit_per_minute,
+            trust_forwarded_for: env_or("TRUST_X_FORWARDED_FOR", false),
+            api_keys: env_or("API_KEYS", String::new())
+                .split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .map(str::to_string)
+                .collect(),
+            base_host: std::env::var("BASE_HOST")
+                .ok()
+                .filter(|host| !host.is_empty())
+                .map(|host| {
+                    Host::parse(&host).expect("BASE_HOST must be a valid host").to_string()
+                }),
+            base_url,
+            case_insensitive_ids: env_or("CASE_INSENSITIVE_IDS", false),
+            metrics_addr: std::env::var("METRICS_ADDR")
+                .ok()
+                .filter(|addr| !addr.is_empty())
+                .map(|addr| addr.parse().expect("METRICS_ADDR must be a socket address")),
+            link_cache_capacity: env_or("LINK_CACHE_CAPACITY", DEFAULT_LINK_CACHE_CAPACITY),
+            link_cache_ttl_seconds: env_or("LINK_CACHE_TTL_SECONDS", DEFAULT_LINK_CACHE_TTL_SECONDS),
+            bulk_create_max_links: env_or("BULK_CREATE_MAX_LINKS", DEFAULT_BULK_CREATE_MAX_LINKS),
+            bulk_db_timeout_ms: env_or("BULK_DB_TIMEOUT_MS", DEFAULT_BULK_DB_TIMEOUT_MS),
+            delete_links_db_timeout_ms: env_or(
+                "DELETE_LINKS_DB_TIMEOUT_MS",
+                DEFAULT_DELETE_LINKS_DB_TIMEOUT_MS
+            ),
+            shutdown_grace_period_seconds: env_or(
+                "SHUTDOWN_GRACE_PERIOD_SECONDS",
+                DEFAULT_SHUTDOWN_GRACE_PERIOD_SECONDS
+            ),
+            id_length,
+            id_alphabet,
+            honor_do_not_track: env_or("HONOR_DO_NOT_TRACK", true),
+            body_limit_bytes: env_or("BODY_LIMIT_BYTES", DEFAULT_BODY_LIMIT_BYTES),
+            bulk_body_limit_bytes: env_or("BULK_BODY_LIMIT_BYTES", DEFAULT_BULK_BODY_LIMIT_BYTES),
+            max_target_url_length: env_or("MAX_TARGET_URL_LENGTH", DEFAULT_MAX_TARGET_URL_LENGTH),
+            top_links_max_limit,
+            top_referers_max_limit,
+            homepage_url,
+            not_found_redirect,
+            not_found_log_sample_rate,
+            suggest_similar_ids: env_or("SUGGEST_SIMILAR_IDS", false),
+            similar_id_threshold,
+            idempotency_key_ttl_seconds: env_or(
+                "IDEMPOTENCY_KEY_TTL_SECONDS",
+                DEFAULT_IDEMPOTENCY_KEY_TTL_SECONDS
+            ),
+            statistics_sink,
+            count_clicks: env_or("COUNT_CLICKS", false),
+            record_statistics: env_or("RECORD_STATISTICS", true),
+            synchronous_statistics,
+            preserve_query: env_or("PRESERVE_QUERY", false),
+            referrer_policy,
+            allow_redirect_status_override: env_or("ALLOW_REDIRECT_STATUS_OVERRIDE", false),
+            allow_relative_targets: env_or("ALLOW_RELATIVE_TARGETS", false),
+            verify_targets: env_or("VERIFY_TARGETS", false),
+            compress_responses: env_or("COMPRESS_RESPONSES", true),
+            verify_target_timeout_ms: env_or("VERIFY_TARGET_TIMEOUT_MS", DEFAULT_VERIFY_TARGET_TIMEOUT_MS),
+            verify_target_max_redirects: env_or(
+                "VERIFY_TARGET_MAX_REDIRECTS",
+                DEFAULT_VERIFY_TARGET_MAX_REDIRECTS
+            ),
+            allowed_target_domains: domain_patterns("ALLOWED_TARGET_DOMAINS"),
+            blocked_target_domains: domain_patterns("BLOCKED_TARGET_DOMAINS"),
+            metadata_fetch_timeout_ms: env_or("METADATA_FETCH_TIMEOUT_MS", DEFAULT_METADATA_FETCH_TIMEOUT_MS),
+            metadata_max_bytes: env_or("METADATA_MAX_BYTES", DEFAULT_METADATA_MAX_BYTES),
+            metadata_cache_ttl_seconds: env_or(
+                "METADATA_CACHE_TTL_SECONDS",
+                DEFAULT_METADATA_CACHE_TTL_SECONDS
+            ),
+            link_history_max_entries,
+            max_expiry_days,
+            require_expiry: env_or("REQUIRE_EXPIRY", false),
+            reserved_ids,
+            access_log_level: env_or("ACCESS_LOG_LEVEL", Level::INFO),
+            server_timing: env_or("SERVER_TIMING", false),
+            otlp_endpoint: std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+                .ok()
+                .filter(|endpoint| !endpoint.is_empty()),
+            redis_url
+        }
+    }
+
+    /// Builds the public short url for `link_id`.
+    pub fn short_url(&self, link_id: &str) -> String {
+        format!("{}/{}", self.base_url.as_str().trim_end_matches('/'), link_id)
+    }
+
+    /// Builds the url of the `GET /links/{id}` resource of `link_id`.
+    pub fn link_url(&self, link_id: &str) -> String {
+        format!("{}/links/{}", self.base_url.as_str().trim_end_matches('/'), link_id)
+    }
+
+    /// Whether `link_id` names a route or was reserved by the deployment,
+    /// regardless of its casing.
+    pub fn is_reserved_id(&self, link_id: &str) -> bool {
+        self.reserved_ids.contains(&link_id.to_lowercase())
+    }
+
+    pub fn db_timeout(&self) -> Duration {
+        Duration::from_millis(self.db_timeout_ms)
+    }
+
+    pub fn bulk_db_timeout(&self) -> Duration {
+        Duration::from_millis(self.bulk_db_timeout_ms)
+    }
+
+    pub fn delete_links_db_timeout(&self) -> Duration {
+        Duration::from_millis(self.delete_links_db_timeout_ms)
+    }
+
+    pub fn db_acquire_timeout(&self) -> Duration {
+        Duration::from_millis(self.db_acquire_timeout_ms)
+    }
+
+    pub fn db_idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.db_idle_timeout_seconds)
+    }
+
+    pub fn shutdown_grace_period(&self) -> Duration {
+        Duration::from_secs(self.shutdown_grace_period_seconds)
+    }
+
+    pub fn verify_target_timeout(&self) -> Duration {
+        Duration::from_millis(self.verify_target_timeout_ms)
+    }
+
+    pub fn metadata_fetch_timeout(&self) -> Duration {
+        Duration::from_millis(self.metadata_fetch_timeout_ms)
+    }
+
+    pub fn statistics_retention_interval(&self) -> Duration {
+        Duration::from_secs(self.statistics_retention_interval_seconds)
+    }
+
+    pub fn statistics_flush_interval(&self) -> Duration {
+        Duration::from_millis(self.statistics_flush_interval_ms)
+    }
+}
+
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    match std::env::var(key) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("{key} has an invalid value: {value}")),
+        Err(_) => default
+    }
+}
+
+/// Reads an http or https url clients get redirected to, normalized like link
+/// targets, `None` when unset or empty.
+fn redirect_url(key: &str) -> Option<String> {
+    std::env::var(key)
+        .ok()
+        .filter(|url| !url.is_empty())
+        .map(|url| {
+            normalize_target_url(&url)
+                .unwrap_or_else(|err| panic!("{key} must be an http or https url: {err}"))
+        })
+}
+
+/// Reads a comma separated list of `DomainPattern`s, empty when unset.
+fn domain_patterns(key: &str) -> Vec<DomainPattern> {
+    env_or(key, String::new())
+        .split(',')
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .map(|pattern| {
+            DomainPattern::parse(pattern)
+                .unwrap_or_else(|_| panic!("{key} has an invalid domain: {pattern}"))
+        })
+        .collect()
+}
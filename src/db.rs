@@ -0,0 +1,21 @@
+use sqlx::migrate::MigrateError;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::PgPool;
+
+use crate::config::Config;
+
+/// Opens the connection pool shared by the handlers and the statistics writer.
+pub async fn connect(db_url: &str, config: &Config) -> Result<PgPool, sqlx::Error> {
+    PgPoolOptions::new()
+        .max_connections(config.db_max_connections)
+        .acquire_timeout(config.db_acquire_timeout())
+        .idle_timeout(config.db_idle_timeout())
+        .connect(db_url)
+        .await
+}
+
+/// Applies the migrations in `migrations/` that the database hasn't seen yet.
+/// They are embedded at compile time, so the binary doesn't need the directory.
+pub async fn migrate(pool: &PgPool) -> Result<(), MigrateError> {
+    sqlx::migrate!().run(pool).await
+}
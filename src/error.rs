@@ -0,0 +1,89 @@
+use axum::http::{header::ALLOW, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use utoipa::ToSchema;
+
+use crate::telemetry::current_request_id;
+
+/// Error returned by handlers. It is rendered as
+/// `{ "error": { "code": "NOT_FOUND", "message": "..." } }` with `status` as
+/// the response status.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+    /// Similar link ids offered on a redirect miss, see `SUGGEST_SIMILAR_IDS`.
+    pub suggestions: Option<Vec<String>>
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct ErrorBody {
+    error: ErrorDetails
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct ErrorDetails {
+    code: String,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suggestions: Option<Vec<String>>,
+    /// Id of the failed request, also found in the logs, on server errors only.
+    #[serde(rename = "requestId", skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            suggestions: None
+        }
+    }
+
+    pub fn with_suggestions(mut self, suggestions: Vec<String>) -> Self {
+        self.suggestions = Some(suggestions);
+        self
+    }
+
+    /// Machine readable code derived from the status, e.g. `TOO_MANY_REQUESTS`.
+    pub fn code(&self) -> String {
+        self.status
+            .canonical_reason()
+            .unwrap_or("UNKNOWN")
+            .to_uppercase()
+            .replace([' ', '-'], "_")
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody {
+            error: ErrorDetails {
+                code: self.code(),
+                message: self.message,
+                suggestions: self.suggestions,
+                request_id: current_request_id().filter(|_| self.status.is_server_error())
+            }
+        };
+
+        (self.status, Json(body)).into_response()
+    }
+}
+
+/// Gives the empty 405 the router answers unsupported methods with the same
+/// body as every other error, keeping its `Allow` header.
+pub async fn method_not_allowed_body(response: Response) -> Response {
+    if response.status() != StatusCode::METHOD_NOT_ALLOWED {
+        return response;
+    }
+
+    let allow = response.headers().get(ALLOW).cloned();
+    let mut response = ApiError::new(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed").into_response();
+
+    if let Some(allow) = allow {
+        response.headers_mut().insert(ALLOW, allow);
+    }
+
+    response
+}
@@ -0,0 +1,169 @@
+use std::sync::Arc;
+
+use axum::http::{HeaderMap, StatusCode};
+use chrono::{DateTime, Utc};
+use sha3::{Digest, Sha3_256};
+use sqlx::{PgExecutor, PgPool};
+use tokio::time::Duration;
+
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::routes::{query_link, Link, LinkTarget};
+use crate::utils::{database_error, internal_error, timed};
+
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+/// `Idempotency-Key` of a create request together with a hash of its body, so
+/// a reused key can be told apart from a retry of the same request.
+pub struct IdempotentRequest {
+    pub key: String,
+    pub request_hash: String
+}
+
+impl IdempotentRequest {
+    pub fn from_headers(headers: &HeaderMap, new_link: &LinkTarget) -> Result<Option<Self>, ApiError> {
+        let Some(key) = headers.get("idempotency-key") else {
+            return Ok(None);
+        };
+
+        let key = key
+            .to_str()
+            .ok()
+            .filter(|key| !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LENGTH)
+            .ok_or_else(|| ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "idempotency key must be between 1 and {} visible characters",
+                    MAX_IDEMPOTENCY_KEY_LENGTH
+                )
+            ))?;
+
+        let body = serde_json::to_vec(new_link).map_err(internal_error)?;
+
+        let mut hasher = Sha3_256::new();
+        hasher.update(&body);
+        let request_hash = hasher.finalize();
+
+        Ok(Some(Self {
+            key: key.to_string(),
+            request_hash: format!("{request_hash:x}")
+        }))
+    }
+}
+
+fn expires_before(config: &Config) -> DateTime<Utc> {
+    Utc::now() - chrono::Duration::seconds(config.idempotency_key_ttl_seconds as i64)
+}
+
+/// Returns the link created by an earlier request with the same key, or a 409
+/// when that request had a different body.
+pub async fn find_link(
+    pool: &PgPool,
+    config: &Config,
+    request: &IdempotentRequest
+) -> Result<Option<Link>, ApiError> {
+    let select_timeout = config.db_timeout();
+
+    let stored_request = timed("select_idempotency_key", tokio::time::timeout(
+        select_timeout,
+        async {
+            let stored_key = sqlx::query!(
+                r#"
+                    select request_hash, link_id from idempotency_keys
+                    where key = $1 and created_at > $2
+                "#,
+                &request.key,
+                expires_before(config)
+            )
+            .fetch_optional(pool)
+            .await?;
+
+            let Some(stored_key) = stored_key else {
+                return Ok(None);
+            };
+
+            let link = query_link!(
+                "select",
+                r#"
+                    from links where id = $1
+                "#,
+                &stored_key.link_id
+            )
+            .fetch_optional(pool)
+            .await?;
+
+            Ok::<_, sqlx::Error>(link.map(|link| (stored_key.request_hash, link)))
+        }
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?;
+
+    match stored_request {
+        Some((request_hash, _)) if request_hash != request.request_hash => Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "idempotency key was already used for a different request"
+        )),
+        Some((_, link)) => Ok(Some(link)),
+        None => Ok(None)
+    }
+}
+
+/// Claims `request.key` for `link_id`. Expired keys are taken over, so this
+/// only returns `false` when another request holds the key.
+pub async fn store<'e, E>(
+    executor: E,
+    config: &Config,
+    request: &IdempotentRequest,
+    link_id: &str
+) -> Result<bool, sqlx::Error>
+where E: PgExecutor<'e>,
+{
+    let stored_key = sqlx::query!(
+        r#"
+            insert into idempotency_keys(key, request_hash, link_id)
+            values($1, $2, $3)
+            on conflict (key) do update set
+                request_hash = excluded.request_hash,
+                link_id = excluded.link_id,
+                created_at = now()
+            where idempotency_keys.created_at <= $4
+        "#,
+        &request.key,
+        &request.request_hash,
+        link_id,
+        expires_before(config)
+    )
+    .execute(executor)
+    .await?;
+
+    Ok(stored_key.rows_affected() == 1)
+}
+
+pub fn spawn_cleanup(pool: PgPool, config: Arc<Config>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut cleanup_interval = tokio::time::interval(interval);
+
+        loop {
+            cleanup_interval.tick().await;
+
+            let deleted_keys = timed("delete_idempotency_keys", sqlx::query!(
+                "delete from idempotency_keys where created_at <= $1",
+                expires_before(&config)
+            )
+            .execute(&pool))
+            .await;
+
+            match deleted_keys {
+                Ok(deleted_keys) => tracing::debug!(
+                    "Deleted {} expired idempotency keys",
+                    deleted_keys.rows_affected()
+                ),
+                Err(err) => tracing::error!(
+                    "Deleting expired idempotency keys failed with the following error: {}",
+                    err
+                )
+            }
+        }
+    });
+}
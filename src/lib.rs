@@ -0,0 +1,194 @@
+mod routes;
+mod utils;
+mod auth;
+mod cache;
+pub mod config;
+pub mod db;
+mod error;
+pub mod idempotency;
+mod metadata;
+mod openapi;
+mod password;
+mod rate_limit;
+mod retry;
+pub mod state;
+pub mod statistics;
+mod target_check;
+pub mod telemetry;
+
+use std::error::Error;
+use std::sync::Arc;
+
+use axum::{extract::DefaultBodyLimit, middleware, routing::{delete, get, patch, post, put}, Router};
+use routes::{
+    count_links, create_link, create_links_bulk, delete_link, delete_links, disable_link,
+    enable_link, get_campaign_statistics, get_link, get_link_clicks_ndjson,
+    get_link_device_statistic, get_link_heatmap, get_link_history, get_link_locale_targets,
+    get_link_metadata, get_link_qr_code, get_link_statistic, get_link_statistic_csv,
+    get_link_timeline, get_link_total_clicks, get_link_variants, get_links_total_clicks,
+    get_stale_links, get_top_links, get_top_referers, health, list_campaign_links, list_links,
+    list_owned_links, patch_link, preview_link, purge_link_statistics, ready, redirect, root,
+    replace_link_locale_targets, replace_link_variants, rotate_link, service_status, update_link,
+    validate_link
+};
+use sqlx::PgPool;
+use tower_http::trace::TraceLayer;
+use auth::auth;
+use error::method_not_allowed_body;
+use openapi::openapi_json;
+use tower_http::compression::CompressionLayer;
+use cache::LinkCache;
+use state::AppState;
+use config::Config;
+use rate_limit::{rate_limit, RateLimiter};
+use statistics::StatisticsWriter;
+
+/// Builds the shared state on top of a migrated pool. The returned writer
+/// holds the buffered clicks and has to be shut down once serving stopped.
+pub async fn build_state(
+    pool: PgPool,
+    config: Config
+) -> Result<(AppState, StatisticsWriter), Box<dyn Error>> {
+    let statistics_sink = statistics::connect_sink(pool.clone(), &config).await?;
+    let (statistics, statistics_writer) =
+        statistics::spawn_writer(statistics_sink.clone(), &config);
+
+    let rate_limiter = Arc::new(RateLimiter::new(config.rate_limit_per_minute));
+    rate_limit::spawn_eviction(rate_limiter.clone(), tokio::time::Duration::from_secs(60));
+
+    let target_checker = if config.verify_targets {
+        Some(target_check::TargetChecker::new(&config)?)
+    } else {
+        None
+    };
+
+    let metadata_fetcher = metadata::MetadataFetcher::new(&config)?;
+
+    let app_state = AppState {
+        pool,
+        link_cache: LinkCache::new(&config),
+        config: Arc::new(config),
+        statistics,
+        statistics_sink,
+        rate_limiter,
+        target_checker,
+        metadata_fetcher,
+        started_at: tokio::time::Instant::now()
+    };
+
+    Ok((app_state, statistics_writer))
+}
+
+/// Every route of the service except `/metrics`, which `main` mounts here or
+/// on its own listener depending on `METRICS_ADDR`.
+pub fn routes(app_state: &AppState) -> Router<AppState> {
+    // Layered per method router rather than on the whole router, so a request
+    // with an unsupported method gets its 405 before being asked for a key.
+    let authenticated = || middleware::from_fn_with_state(app_state.clone(), auth);
+
+    Router::new()
+        .route("/create",
+            post(create_link)
+            .layer(DefaultBodyLimit::max(app_state.config.body_limit_bytes))
+            .route_layer(middleware::from_fn_with_state(app_state.clone(), rate_limit))
+            .route_layer(authenticated()))
+        .route("/links/bulk",
+            post(create_links_bulk)
+            .layer(DefaultBodyLimit::max(app_state.config.bulk_body_limit_bytes))
+            .route_layer(middleware::from_fn_with_state(app_state.clone(), rate_limit))
+            .route_layer(authenticated()))
+        .route("/:id/statistics", get(get_link_statistic).route_layer(authenticated()))
+        .route("/links", get(list_links).delete(delete_links).route_layer(authenticated()))
+        .route("/links/mine", get(list_owned_links).route_layer(authenticated()))
+        .route("/links/count", get(count_links).route_layer(authenticated()))
+        .route("/campaigns/:id/links", get(list_campaign_links).route_layer(authenticated()))
+        .route(
+            "/campaigns/:id/statistics",
+            get(get_campaign_statistics).route_layer(authenticated())
+        )
+        .route("/links/stale", get(get_stale_links).route_layer(authenticated()))
+        .route("/links/top", get(get_top_links).route_layer(authenticated()))
+        .route("/statistics/referers/top", get(get_top_referers).route_layer(authenticated()))
+        .route("/links/statistics", post(get_links_total_clicks).route_layer(authenticated()))
+        .route("/links/validate",
+            post(validate_link)
+            .layer(DefaultBodyLimit::max(app_state.config.body_limit_bytes))
+            .route_layer(authenticated()))
+        .route("/links/:id",
+            patch(patch_link)
+            .layer(DefaultBodyLimit::max(app_state.config.body_limit_bytes))
+            .route_layer(middleware::from_fn_with_state(app_state.clone(), rate_limit))
+            .get(get_link)
+            .route_layer(authenticated()))
+        .route(
+            "/links/:id/statistics",
+            delete(purge_link_statistics).route_layer(authenticated())
+        )
+        .route("/links/:id/variants",
+            put(replace_link_variants)
+            .layer(DefaultBodyLimit::max(app_state.config.body_limit_bytes))
+            .route_layer(middleware::from_fn_with_state(app_state.clone(), rate_limit))
+            .get(get_link_variants)
+            .route_layer(authenticated()))
+        .route("/links/:id/locales",
+            put(replace_link_locale_targets)
+            .layer(DefaultBodyLimit::max(app_state.config.body_limit_bytes))
+            .route_layer(middleware::from_fn_with_state(app_state.clone(), rate_limit))
+            .get(get_link_locale_targets)
+            .route_layer(authenticated()))
+        .route("/links/:id/statistics.csv", get(get_link_statistic_csv).route_layer(authenticated()))
+        .route(
+            "/links/:id/statistics.ndjson",
+            get(get_link_clicks_ndjson).route_layer(authenticated())
+        )
+        .route(
+            "/links/:id/statistics/devices",
+            get(get_link_device_statistic).route_layer(authenticated())
+        )
+        .route("/links/:id/clicks", get(get_link_total_clicks).route_layer(authenticated()))
+        .route("/links/:id/timeline", get(get_link_timeline).route_layer(authenticated()))
+        .route("/links/:id/history", get(get_link_history).route_layer(authenticated()))
+        .route("/links/:id/heatmap", get(get_link_heatmap).route_layer(authenticated()))
+        .route("/links/:id/disable", patch(disable_link).route_layer(authenticated()))
+        .route("/links/:id/enable", patch(enable_link).route_layer(authenticated()))
+        .route("/links/:id/rotate", post(rotate_link).route_layer(authenticated()))
+        .route("/:id", 
+            patch(update_link)
+            .layer(DefaultBodyLimit::max(app_state.config.body_limit_bytes))
+            .route_layer(middleware::from_fn_with_state(app_state.clone(), rate_limit))
+            .delete(delete_link)
+            .route_layer(authenticated())
+            .get(redirect))
+        .route("/:id/", get(redirect))
+        .route("/:id/*path", get(redirect))
+        .route("/links/:id/qr", get(get_link_qr_code))
+        .route("/links/:id/preview", get(preview_link))
+        .route("/links/:id/metadata", get(get_link_metadata).route_layer(authenticated()))
+        // `get` routes answer HEAD as well, with the same status and headers
+        // but no body, which is what load balancer probes send.
+        .route("/", get(root))
+        .route("/health", get(health))
+        .route("/ready", get(ready))
+        .route("/status", get(service_status))
+        .route("/api-docs/openapi.json", get(openapi_json))
+}
+
+/// Wraps `routes` in the middleware every request goes through and binds the state.
+pub fn app(routes: Router<AppState>, app_state: AppState) -> Router {
+    routes
+        .layer(middleware::map_response(method_not_allowed_body))
+        .layer(middleware::from_fn_with_state(app_state.clone(), telemetry::server_timing))
+        .layer(
+            CompressionLayer::new()
+                .gzip(app_state.config.compress_responses)
+                .br(app_state.config.compress_responses)
+        )
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(telemetry::request_span)
+                .on_response(telemetry::access_log(&app_state.config))
+        )
+        // Outside the trace layer, so the request span sees the id.
+        .layer(middleware::from_fn(telemetry::request_id))
+        .with_state(app_state)
+}
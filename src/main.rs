@@ -1,54 +1,77 @@
-mod routes;
-mod utils;
-mod auth;
-
 use std::error::Error;
+use std::future::IntoFuture;
+use std::net::SocketAddr;
+use std::sync::Arc;
 
-use axum::{middleware, routing::{get, patch, post}, Router};
+use axum::{routing::get, Router};
 use axum_prometheus::PrometheusMetricLayer;
-use routes::{create_link, get_link_statistic, health, redirect, update_link};
-use sqlx::postgres::PgPoolOptions;
-use tower_http::trace::TraceLayer;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tokio::signal;
+use tokio::sync::Notify;
 use dotenvy::dotenv;
-use auth::auth;
+use shortner::{config::Config, db, idempotency, statistics, telemetry};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
 
     dotenv().ok();
 
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "link_shortener=debug".into())
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
+    let config = Config::from_env();
+    let tracer_provider = telemetry::init(&config)?;
 
     let db_url = std::env::var("DATABASE_URL").expect("DATABASE_URL is required");
 
-    let db_conn = PgPoolOptions::new()
-        .max_connections(20)
-        .connect(&db_url)
-        .await?;
+    let db_conn = db::connect(&db_url, &config).await?;
+
+    if config.skip_migrations {
+        tracing::info!("Skipping database migrations");
+    } else {
+        db::migrate(&db_conn).await?;
+        tracing::info!("Database migrations applied");
+    }
+
+    let shutdown_grace_period = config.shutdown_grace_period();
+
+    let (app_state, statistics_writer) = shortner::build_state(db_conn.clone(), config).await?;
+
+    idempotency::spawn_cleanup(
+        db_conn.clone(),
+        app_state.config.clone(),
+        tokio::time::Duration::from_secs(60 * 60)
+    );
+
+    match app_state.config.statistics_retention_days {
+        Some(retention_days) => {
+            statistics::spawn_retention(db_conn.clone(), app_state.config.clone(), retention_days)
+        },
+        None => tracing::info!("Statistics retention is disabled, clicks are kept forever")
+    }
 
     let (prometheous_layer, metric_handle) = PrometheusMetricLayer::pair();
+    let render_metrics = || async move { metric_handle.render() };
+
+    let mut app = shortner::routes(&app_state);
+
+    match app_state.config.metrics_addr {
+        Some(metrics_addr) => {
+            let metrics_listener = tokio::net::TcpListener::bind(metrics_addr)
+                .await
+                .expect("Could not initialize metrics TcpListener");
+
+            tracing::debug!("serving metrics on {}", metrics_addr);
+
+            let metrics_app = Router::new().route("/metrics", get(render_metrics));
+
+            tokio::spawn(async move {
+                axum::serve(metrics_listener, metrics_app)
+                .with_graceful_shutdown(shutdown_signal())
+                .await
+                .expect("Could not initiate metrics server");
+            });
+        },
+        None => app = app.route("/metrics", get(render_metrics))
+    }
 
-    let app: Router<()> = Router::new()
-        .route("/create", post(create_link))
-        .route("/:id/statistics", get(get_link_statistic))
-        .route_layer(middleware::from_fn_with_state(db_conn.clone(), auth))
-        .route("/:id", 
-            patch(update_link)
-            .route_layer(middleware::from_fn_with_state(db_conn.clone(), auth))
-            .get(redirect))
-        .route("/metrics", get(|| async move {metric_handle.render()}))
-        .route("/health", get(health))
-        .layer(TraceLayer::new_for_http())
-        .layer(prometheous_layer)
-        .with_state(db_conn);
+    let app = shortner::app(app, app_state).layer(prometheous_layer);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000")
         .await
@@ -61,9 +84,68 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .expect("Could not convert listener address to local address")
     );
 
-    axum::serve(listener, app)
-    .await
-    .expect("Could initiate server");
+    let draining = Arc::new(Notify::new());
+
+    let server = axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown({
+            let draining = draining.clone();
+
+            async move {
+                shutdown_signal().await;
+                tracing::info!("Shutdown signal received, draining in-flight requests");
+                draining.notify_one();
+            }
+        })
+        .into_future();
+
+    let grace_period_elapsed = async {
+        draining.notified().await;
+        tokio::time::sleep(shutdown_grace_period).await;
+    };
+
+    tokio::select! {
+        served = server => {
+            served.expect("Could initiate server");
+            tracing::info!("All in-flight requests completed");
+        },
+        _ = grace_period_elapsed => tracing::warn!(
+            "Grace period of {:?} elapsed, dropping remaining connections",
+            shutdown_grace_period
+        )
+    }
+
+    statistics_writer.shutdown().await?;
+    db_conn.close().await;
+
+    if let Some(tracer_provider) = tracer_provider {
+        tracer_provider.shutdown()?;
+    }
+
+    tracing::info!("Shutdown complete");
     
     Ok(())
 }
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("Could not install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("Could not install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {}
+    }
+}
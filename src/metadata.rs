@@ -0,0 +1,423 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+
+use axum::http::header::ACCEPT;
+use moka::future::Cache;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use reqwest::Client;
+use tokio::time::Duration;
+use url::{Host, Url};
+use utoipa::ToSchema;
+
+use crate::config::Config;
+
+const METADATA_CACHE_CAPACITY: u64 = 10_000;
+const METADATA_MAX_REDIRECTS: usize = 5;
+
+/// Open Graph tags of a link's target, returned by `GET /links/{id}/metadata`.
+#[derive(Clone, Default, serde::Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkMetadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// Absolute url, resolved against the target when the page gave a relative one.
+    pub image: Option<String>,
+    /// Why the target could not be read completely, the other fields hold
+    /// whatever was found before that.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fetch_error: Option<String>
+}
+
+/// Fetches the HTML of link targets and extracts their Open Graph tags. Only
+/// the first `METADATA_MAX_BYTES` of a page are read, which is where the
+/// `<head>` is. Targets, and every hop they redirect to, must be publicly
+/// routable, so links can't be used to read internal services. Complete results are cached per link for
+/// `METADATA_CACHE_TTL_SECONDS`, failed fetches are retried on the next request.
+#[derive(Clone)]
+pub struct MetadataFetcher {
+    client: Client,
+    /// Keyed by link id, holding the target the metadata was read from so a
+    /// changed target is fetched again.
+    cache: Cache<String, (String, LinkMetadata)>,
+    max_bytes: usize
+}
+
+impl MetadataFetcher {
+    pub fn new(config: &Config) -> Result<Self, reqwest::Error> {
+        let client = Client::builder()
+            .timeout(config.metadata_fetch_timeout())
+            .redirect(Policy::custom(|attempt| {
+                if attempt.previous().len() >= METADATA_MAX_REDIRECTS {
+                    attempt.error("too many redirects")
+                } else if is_non_global_ip_host(attempt.url()) {
+                    attempt.error(NonGlobalAddress)
+                } else {
+                    attempt.follow()
+                }
+            }))
+            .dns_resolver(Arc::new(GlobalResolver))
+            // A proxy would resolve hosts itself, past `GlobalResolver`.
+            .no_proxy()
+            .build()?;
+
+        let cache = Cache::builder()
+            .max_capacity(METADATA_CACHE_CAPACITY)
+            .time_to_live(Duration::from_secs(config.metadata_cache_ttl_seconds))
+            .build();
+
+        Ok(Self {
+            client,
+            cache,
+            max_bytes: config.metadata_max_bytes
+        })
+    }
+
+    pub async fn fetch(&self, link_id: &str, target_url: &str) -> LinkMetadata {
+        if let Some((cached_target, metadata)) = self.cache.get(link_id).await {
+            if cached_target == target_url {
+                return metadata;
+            }
+        }
+
+        let metadata = self.fetch_uncached(target_url).await;
+
+        if metadata.fetch_error.is_none() {
+            self.cache
+                .insert(link_id.to_string(), (target_url.to_string(), metadata.clone()))
+                .await;
+        }
+
+        metadata
+    }
+
+    async fn fetch_uncached(&self, target_url: &str) -> LinkMetadata {
+        // Ip hosts are connected to without asking `GlobalResolver`.
+        if Url::parse(target_url).is_ok_and(|url| is_non_global_ip_host(&url)) {
+            tracing::debug!("Not fetching metadata of {}, its host is not a public address", target_url);
+
+            return LinkMetadata {
+                fetch_error: Some(NonGlobalAddress.to_string()),
+                ..LinkMetadata::default()
+            };
+        }
+
+        let mut response = match self.client.get(target_url).header(ACCEPT, "text/html").send().await {
+            Ok(response) => response,
+            Err(err) => {
+                tracing::debug!("Fetching metadata of {} failed: {}", target_url, err);
+
+                return LinkMetadata {
+                    fetch_error: Some(fetch_error_reason(&err).to_string()),
+                    ..LinkMetadata::default()
+                };
+            }
+        };
+
+        let status = response.status();
+
+        if !status.is_success() {
+            tracing::debug!("Fetching metadata of {} answered with {}", target_url, status);
+
+            return LinkMetadata {
+                fetch_error: Some(format!("target url answered with {}", status)),
+                ..LinkMetadata::default()
+            };
+        }
+
+        let mut body = Vec::new();
+        let mut fetch_error = None;
+
+        while body.len() < self.max_bytes {
+            match response.chunk().await {
+                Ok(Some(chunk)) => {
+                    let remaining = self.max_bytes - body.len();
+                    body.extend_from_slice(&chunk[..chunk.len().min(remaining)]);
+                },
+                Ok(None) => break,
+                Err(err) => {
+                    tracing::debug!("Reading metadata of {} failed: {}", target_url, err);
+
+                    fetch_error = Some(fetch_error_reason(&err).to_string());
+                    break;
+                }
+            }
+        }
+
+        let mut metadata = parse_open_graph(&String::from_utf8_lossy(&body), target_url);
+        metadata.fetch_error = fetch_error;
+
+        metadata
+    }
+}
+
+fn fetch_error_reason(err: &reqwest::Error) -> &'static str {
+    let mut source: Option<&(dyn Error + 'static)> = Some(err);
+
+    while let Some(err) = source {
+        if err.is::<NonGlobalAddress>() {
+            return "target url points to a non-public address";
+        }
+
+        source = err.source();
+    }
+
+    if err.is_redirect() {
+        "target url redirects too often"
+    } else if err.is_timeout() {
+        "target url did not respond in time"
+    } else {
+        "target url is unreachable"
+    }
+}
+
+/// Refusal to connect to an address `is_global_ip` rejects.
+#[derive(Debug)]
+struct NonGlobalAddress;
+
+impl fmt::Display for NonGlobalAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "target url points to a non-public address")
+    }
+}
+
+impl Error for NonGlobalAddress {}
+
+/// Resolves hosts through the system resolver, keeping only publicly
+/// routable addresses. The connection goes to what this returned, so a
+/// host can't switch to an internal address after being checked.
+struct GlobalResolver;
+
+impl Resolve for GlobalResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|address| is_global_ip(address.ip()))
+                .collect();
+
+            if addresses.is_empty() {
+                return Err(NonGlobalAddress.into());
+            }
+
+            let addresses: Addrs = Box::new(addresses.into_iter());
+            Ok(addresses)
+        })
+    }
+}
+
+fn is_non_global_ip_host(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Ipv4(ip)) => !is_global_ip(IpAddr::V4(ip)),
+        Some(Host::Ipv6(ip)) => !is_global_ip(IpAddr::V6(ip)),
+        _ => false
+    }
+}
+
+/// Whether `ip` is publicly routable. `IpAddr::is_global` is not stable yet,
+/// so the special purpose ranges are listed here.
+fn is_global_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // This network, shared address space, protocol assignments,
+                // benchmarking and reserved.
+                || a == 0
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 192 && b == 0 && c == 0)
+                || (a == 198 && (18..20).contains(&b))
+                || a >= 240)
+        },
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_global_ip(IpAddr::V4(ip));
+            }
+
+            let segments = ip.segments();
+
+            // NAT64 addresses reach the ipv4 address in their last 32 bits.
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                let [.., high, low] = segments;
+                return is_global_ip(IpAddr::V4(Ipv4Addr::from((u32::from(high) << 16) | u32::from(low))));
+            }
+
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                // Unique local, link local and documentation.
+                || (segments[0] & 0xfe00) == 0xfc00
+                || (segments[0] & 0xffc0) == 0xfe80
+                || (segments[0] == 0x2001 && segments[1] == 0xdb8))
+        }
+    }
+}
+
+/// Reads `og:title`, `og:description` and `og:image` from the `<meta>` tags
+/// of `html`, keeping the first occurrence of each.
+fn parse_open_graph(html: &str, target_url: &str) -> LinkMetadata {
+    let mut metadata = LinkMetadata::default();
+    // Lowercasing ascii keeps byte offsets, so matches index into `html` too.
+    let lowercase_html = html.to_ascii_lowercase();
+    let mut position = 0;
+
+    while let Some(tag_start) = lowercase_html[position..].find("<meta") {
+        let attributes_start = position + tag_start + "<meta".len();
+
+        let Some(tag_length) = lowercase_html[attributes_start..].find('>') else {
+            break;
+        };
+
+        let attributes_end = attributes_start + tag_length;
+        position = attributes_end;
+
+        let attributes = tag_attributes(&html[attributes_start..attributes_end]);
+
+        let (Some(property), Some(content)) = (
+            attributes.get("property").or_else(|| attributes.get("name")),
+            attributes.get("content")
+        ) else {
+            continue;
+        };
+
+        let field = match property.to_ascii_lowercase().as_str() {
+            "og:title" => &mut metadata.title,
+            "og:description" => &mut metadata.description,
+            "og:image" => &mut metadata.image,
+            _ => continue
+        };
+
+        if field.is_none() && !content.trim().is_empty() {
+            *field = Some(decode_entities(content.trim()));
+        }
+    }
+
+    metadata.image = metadata.image.and_then(|image| {
+        Url::parse(target_url).and_then(|url| url.join(&image)).ok().map(String::from)
+    });
+
+    metadata
+}
+
+/// Splits the inside of a tag into lowercased attribute names and their
+/// values, quoted or not.
+fn tag_attributes(tag: &str) -> HashMap<String, String> {
+    let mut attributes = HashMap::new();
+    let mut rest = tag;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+
+        if rest.is_empty() {
+            break;
+        }
+
+        let name_end = rest
+            .find(|c: char| c.is_ascii_whitespace() || c == '=' || c == '/')
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        let value = match rest.strip_prefix('=').map(str::trim_start) {
+            Some(quoted) if quoted.starts_with(['"', '\'']) => {
+                let quote = &quoted[..1];
+                let value_end = quoted[1..].find(quote).map_or(quoted.len(), |end| end + 1);
+                rest = quoted.get(value_end + 1..).unwrap_or_default();
+
+                &quoted[1..value_end]
+            },
+            Some(unquoted) => {
+                let value_end = unquoted.find(|c: char| c.is_ascii_whitespace()).unwrap_or(unquoted.len());
+                rest = &unquoted[value_end..];
+
+                &unquoted[..value_end]
+            },
+            None => ""
+        };
+
+        attributes.entry(name).or_insert_with(|| value.to_string());
+    }
+
+    attributes
+}
+
+/// Decodes the entities that commonly show up in attribute values.
+fn decode_entities(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn is_global_ip_rejects_special_purpose_ranges() {
+        let cases = [
+            ("93.184.215.14", true),
+            ("8.8.8.8", true),
+            ("2606:4700::1111", true),
+            ("::ffff:93.184.215.14", true),
+            ("127.0.0.1", false),
+            ("10.1.2.3", false),
+            ("172.16.0.1", false),
+            ("192.168.1.1", false),
+            ("169.254.169.254", false),
+            ("100.64.0.1", false),
+            ("0.0.0.0", false),
+            ("198.18.0.1", false),
+            ("240.0.0.1", false),
+            ("255.255.255.255", false),
+            ("::1", false),
+            ("::", false),
+            ("fd00::1", false),
+            ("fe80::1", false),
+            ("2001:db8::1", false),
+            ("::ffff:127.0.0.1", false),
+            ("64:ff9b::a9fe:a9fe", false)
+        ];
+
+        for (ip, expected) in cases {
+            assert_eq!(is_global_ip(ip.parse().unwrap()), expected, "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_refuses_non_public_targets() {
+        let fetcher = MetadataFetcher::new(&Config::from_env()).unwrap();
+
+        let target_urls = [
+            "http://127.0.0.1:3000/",
+            "http://[::1]/",
+            "http://169.254.169.254/latest",
+            "http://localhost/"
+        ];
+
+        for target_url in target_urls {
+            let metadata = fetcher.fetch("abc", target_url).await;
+
+            assert_eq!(
+                metadata.fetch_error.as_deref(),
+                Some("target url points to a non-public address"),
+                "{target_url}"
+            );
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use axum::Json;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::error::{ErrorBody, ErrorDetails};
+use crate::metadata::LinkMetadata;
+use crate::routes::{
+    self, CampaignStatistics, ClickRecord, CountedLinkStatistic, CreatedLink, DeletedLinks,
+    DeviceStatistic, HeatmapCell, Link, LinkCount, LinkHistoryEntry, LinkIds, LinkLocaleTarget,
+    LinkPreview, LinkTarget, LinkVariant, PurgedStatistics, ServiceInfo, ServiceStatus, TargetUrl,
+    TargetUrlValidation, TimelineBucket, TimelineBucketSize, TopLink, TopReferer, TotalClicks,
+    UpdateLink
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        routes::root,
+        routes::health,
+        routes::ready,
+        routes::service_status,
+        routes::redirect,
+        routes::get_link,
+        routes::list_links,
+        routes::list_owned_links,
+        routes::count_links,
+        routes::list_campaign_links,
+        routes::get_campaign_statistics,
+        routes::preview_link,
+        routes::get_link_metadata,
+        routes::create_link,
+        routes::create_links_bulk,
+        routes::update_link,
+        routes::patch_link,
+        routes::get_link_variants,
+        routes::replace_link_variants,
+        routes::get_link_locale_targets,
+        routes::replace_link_locale_targets,
+        routes::disable_link,
+        routes::enable_link,
+        routes::rotate_link,
+        routes::delete_link,
+        routes::delete_links,
+        routes::get_link_statistic,
+        routes::get_link_statistic_csv,
+        routes::get_link_clicks_ndjson,
+        routes::purge_link_statistics,
+        routes::get_link_device_statistic,
+        routes::get_link_total_clicks,
+        routes::get_links_total_clicks,
+        routes::validate_link,
+        routes::get_link_qr_code,
+        routes::get_link_timeline,
+        routes::get_link_heatmap,
+        routes::get_link_history,
+        routes::get_stale_links,
+        routes::get_top_links,
+        routes::get_top_referers
+    ),
+    components(schemas(
+        ServiceInfo,
+        ServiceStatus,
+        Link,
+        LinkCount,
+        CampaignStatistics,
+        CreatedLink,
+        LinkPreview,
+        LinkMetadata,
+        LinkTarget,
+        UpdateLink,
+        LinkVariant,
+        LinkLocaleTarget,
+        LinkHistoryEntry,
+        LinkIds,
+        TargetUrl,
+        TargetUrlValidation,
+        ClickRecord,
+        CountedLinkStatistic,
+        DeviceStatistic,
+        PurgedStatistics,
+        DeletedLinks,
+        TimelineBucket,
+        TimelineBucketSize,
+        HeatmapCell,
+        TotalClicks,
+        TopLink,
+        TopReferer,
+        ErrorBody,
+        ErrorDetails
+    )),
+    modifiers(&SecurityAddon)
+)]
+pub struct ApiDoc;
+
+/// Registers the two ways `auth` accepts an API key.
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("x-api-key")))
+        );
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build())
+        );
+    }
+}
+
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
@@ -0,0 +1,34 @@
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+
+use crate::error::ApiError;
+use crate::utils::internal_error;
+
+/// Hashes a link password into the PHC string stored in `links.password_hash`.
+/// Argon2 is deliberately slow, so it runs on the blocking pool.
+pub async fn hash(password: String) -> Result<String, ApiError> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|password_hash| password_hash.to_string())
+            .map_err(internal_error)
+    })
+    .await
+    .map_err(internal_error)?
+}
+
+/// Checks `password` against a hash produced by `hash`.
+pub async fn verify(password_hash: String, password: String) -> Result<bool, ApiError> {
+    tokio::task::spawn_blocking(move || {
+        let password_hash = PasswordHash::new(&password_hash).map_err(internal_error)?;
+
+        Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &password_hash)
+            .is_ok())
+    })
+    .await
+    .map_err(internal_error)?
+}
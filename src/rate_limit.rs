@@ -0,0 +1,116 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use dashmap::DashMap;
+use metrics::counter;
+use tokio::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::utils::client_ip;
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant
+}
+
+/// Token bucket per client ip. Each bucket holds up to `capacity` tokens and
+/// refills continuously so that `capacity` requests are allowed per minute.
+pub struct RateLimiter {
+    buckets: DashMap<IpAddr, Bucket>,
+    capacity: f64,
+    refill_per_second: f64
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_minute: u32) -> Self {
+        let capacity = f64::from(requests_per_minute);
+
+        Self {
+            buckets: DashMap::new(),
+            capacity,
+            refill_per_second: capacity / 60.0
+        }
+    }
+
+    /// Takes a token for `ip`, or returns how long until the next one is available.
+    fn try_acquire(&self, ip: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+
+        let mut bucket = self.buckets.entry(ip).or_insert_with(|| Bucket {
+            tokens: self.capacity,
+            last_refill: now
+        });
+
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        let missing_tokens = 1.0 - bucket.tokens;
+
+        Err(Duration::from_secs_f64(missing_tokens / self.refill_per_second))
+    }
+
+    /// Drops buckets that would have refilled completely by now, since a fresh
+    /// bucket for the same ip would be indistinguishable from them.
+    pub fn evict_idle(&self) {
+        let now = Instant::now();
+        let before = self.buckets.len();
+
+        self.buckets.retain(|_, bucket| {
+            let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+            bucket.tokens + elapsed * self.refill_per_second < self.capacity
+        });
+
+        tracing::debug!(
+            "Evicted {} idle rate limit buckets",
+            before - self.buckets.len()
+        );
+    }
+}
+
+pub fn spawn_eviction(rate_limiter: Arc<RateLimiter>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut eviction_interval = tokio::time::interval(interval);
+
+        loop {
+            eviction_interval.tick().await;
+            rate_limiter.evict_idle();
+        }
+    });
+}
+
+pub async fn rate_limit(
+    State(rate_limiter): State<Arc<RateLimiter>>,
+    State(config): State<Arc<Config>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next
+) -> Response {
+    let ip = client_ip(req.headers(), peer, config.trust_forwarded_for);
+
+    if let Err(retry_after) = rate_limiter.try_acquire(ip) {
+        tracing::error!("Rate limit exceeded for {}", ip);
+
+        let labels = [("uri", format!("{}", req.uri()))];
+        counter!("rate_limited_calls_count", &labels).increment(1);
+
+        let retry_after_seconds = retry_after.as_secs_f64().ceil() as u64;
+
+        return (
+            [(header::RETRY_AFTER, retry_after_seconds.to_string())],
+            ApiError::new(StatusCode::TOO_MANY_REQUESTS, "Too many requests")
+        ).into_response();
+    }
+
+    next.run(req).await
+}
@@ -0,0 +1,66 @@
+use std::fmt::Display;
+use std::future::Future;
+
+use metrics::counter;
+use tokio::time::Duration;
+
+use crate::statistics::StatisticsError;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Errors after which the same query is likely to succeed once the pool has
+/// reconnected, e.g. during a database failover.
+pub trait Transient {
+    fn is_transient(&self) -> bool;
+}
+
+impl Transient for sqlx::Error {
+    fn is_transient(&self) -> bool {
+        match self {
+            sqlx::Error::Io(_) => true,
+            // Class 08 are connection exceptions, 57P01 to 57P03 a server
+            // shutting down or not accepting connections yet.
+            sqlx::Error::Database(db_err) => db_err.code().is_some_and(|code| {
+                code.starts_with("08") || matches!(code.as_ref(), "57P01" | "57P02" | "57P03")
+            }),
+            _ => false
+        }
+    }
+}
+
+impl Transient for StatisticsError {
+    fn is_transient(&self) -> bool {
+        match self {
+            StatisticsError::Database(err) => err.is_transient(),
+            _ => false
+        }
+    }
+}
+
+/// Runs `query` again, up to `retries` times, while it fails with a transient
+/// error, doubling the pause between attempts. The caller's timeout bounds
+/// the total time. Only meant for reads, a retried write may apply twice.
+pub async fn retry_reads<T, E, F, Fut>(query: &'static str, retries: u32, mut attempt: F) -> Result<T, E>
+where
+    E: Transient + Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut backoff = INITIAL_BACKOFF;
+    let mut retried = 0;
+
+    loop {
+        match attempt().await {
+            Err(err) if retried < retries && err.is_transient() => {
+                retried += 1;
+
+                tracing::warn!("Retrying {} ({}/{}) after transient error: {}", query, retried, retries, err);
+                counter!("db_query_retries_count", "query" => query).increment(1);
+
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            },
+            result => return result
+        }
+    }
+}
@@ -1,225 +1,212 @@
+/// `/`, health, readiness and status endpoints.
+mod admin;
+/// Creating, reading, changing and deleting links.
+mod links;
+/// Resolving short links.
+mod redirect;
+/// Reading and purging recorded clicks.
+mod statistics;
 
-
-use axum::body::Body;
-use axum::extract::{Path, State};
-use axum::response::{IntoResponse, Response,};
 use axum::http::{HeaderMap, StatusCode};
-use axum::Json;
-use base64::engine::general_purpose;
-use base64::Engine;
-use rand::Rng;
-use sqlx::PgPool;
-use url::Url;
-
-use crate::utils::internal_error;
-
-const DEFAULT_CACHE_CONTROL_HEADER_VALUE: &str = 
-    "public, max-age=300, s-maxage=300, stale-while-revalidate=300, stale-if-error=300";
-
-#[derive(serde::Deserialize, serde::Serialize)]
+use chrono::{DateTime, Utc};
+use sqlx::{PgExecutor, PgPool};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::auth::Owner;
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::password;
+use crate::utils::{database_error, internal_error, timed};
+
+// Globs, so the `__path_*` items utoipa generates next to every handler come
+// along for `openapi.rs`.
+pub use admin::*;
+pub use links::*;
+pub use redirect::*;
+pub use statistics::*;
+
+#[derive(Clone, serde::Deserialize, serde::Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Link {
      pub id: String,
-     pub target_url: String
+     pub target_url: String,
+     pub permanent: bool,
+     pub expires_at: Option<DateTime<Utc>>,
+     pub enabled: bool,
+     /// When the link was last followed, `None` if it never was.
+     pub last_accessed_at: Option<DateTime<Utc>>,
+     /// Number of redirects after which the link stops resolving.
+     pub max_clicks: Option<i64>,
+     /// Append whatever follows the id in the request path, and the request's
+     /// query, to the target.
+     pub forward_path: bool,
+     /// Overrides the configured `Cache-Control` of the redirect.
+     pub cache_control: Option<String>,
+     /// Free-form labels used to filter `GET /links`.
+     pub tags: Vec<String>,
+     /// Argon2 hash of the password required to follow the link.
+     #[serde(skip)]
+     pub password_hash: Option<String>,
+     /// Overrides the configured `Referrer-Policy` of the redirect.
+     pub referrer_policy: Option<String>,
+     /// Campaign the link is grouped under, `None` for ungrouped links.
+     pub campaign_id: Option<String>,
+     /// Redirects served since `COUNT_CLICKS` was enabled, kept without
+     /// aggregating the statistics.
+     pub click_count: i64
 }
 
-#[derive(serde::Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct LinkTarget {
-    pub target_url: String
+/// `sqlx::query_as!` into a `Link`, with its columns spliced in between
+/// `$before` and `$after`, so a new column is only added here. Selects pass
+/// `"select"` and a query starting at `from links`, writes end `$before` with
+/// `returning` inside a `with` and select `*` from it in `$after`.
+macro_rules! query_link {
+    ($before:literal, $after:literal $(, $arg:expr)* $(,)?) => {
+        sqlx::query_as!(
+            $crate::routes::Link,
+            $before
+                + " id, target_url, permanent, expires_at, enabled, last_accessed_at, max_clicks, \
+                    forward_path, cache_control, tags, password_hash, referrer_policy, campaign_id, \
+                    click_count "
+                + $after
+            $(, $arg)*
+        )
+    };
 }
 
-#[derive(serde::Serialize)]
+pub(crate) use query_link;
+
+/// One of several targets of a link, picked by `redirect` with a probability
+/// of its weight over the sum of all weights.
+#[derive(Clone, serde::Deserialize, serde::Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-pub struct CountedLinkStatistic {
-    pub amount: Option<i64>,
-    pub referer: Option<String>,
-    pub user_agent: Option<String>
+pub struct LinkVariant {
+    pub target_url: String,
+    pub weight: i32
 }
 
-fn generate_id() -> String {
-    let random_number = rand::thread_rng().gen_range(0..u32::MAX);
-    general_purpose::URL_SAFE_NO_PAD.encode(random_number.to_string())
+/// Target served instead of the link's own to visitors whose `Accept-Language`
+/// matches `lang`, a language tag such as `de` or `pt-br`.
+#[derive(Clone, serde::Deserialize, serde::Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkLocaleTarget {
+    pub lang: String,
+    pub target_url: String
 }
 
-pub async fn health() -> impl IntoResponse {
-    (StatusCode::OK, "Service is healthy")
+#[derive(serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Pagination {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>
 }
 
-pub async fn redirect(
-    State(pool): State<PgPool>,
-    Path(requested_link): Path<String>,
-    headers: HeaderMap
-) -> Result<Response, (StatusCode, String)> {
-
-    let select_timeout = tokio::time::Duration::from_millis(300);
-
-    let link = tokio::time::timeout(
-        select_timeout, 
-        sqlx::query_as!(
-        Link, 
-        "select id, target_url from links where id = $1",
-        requested_link
+async fn select_link_variants(
+    executor: impl PgExecutor<'_>,
+    link_id: &str
+) -> Result<Vec<LinkVariant>, sqlx::Error> {
+    sqlx::query_as!(
+        LinkVariant,
+        "select target_url, weight from link_variants where link_id = $1 order by target_url",
+        link_id
     )
-    .fetch_optional(&pool)
-)
-        .await
-        .map_err(internal_error)?
-        .map_err(internal_error)?
-        .ok_or_else(|| "Not found".to_string())
-        .map_err(|err| (StatusCode::NOT_FOUND, err))?;
-
-    tracing::debug!(
-        "Redirecting link id {} to {}",
-        requested_link,
-        link.target_url
-    );
-
-    let referer_header = headers
-        .get("referer")
-        .map(|value| value.to_str().unwrap_or_default().to_string());
-
-    let user_agent_header = headers
-        .get("user-agent")
-        .map(|value| value.to_str().unwrap_or_default().to_string());
-
-    let insert_statistics_timeout = tokio::time::Duration::from_millis(300);
-
-    let saved_statistic = tokio::time::timeout(
-        insert_statistics_timeout,
-        sqlx::query(
-            r#"
-                insert into link_statistics(link_id, referer, user_agent)
-                values($1, $2, $3)
-            "#
-        )
-        .bind(&requested_link)
-        .bind(&referer_header)
-        .bind(&user_agent_header)
-        .execute(&pool)
-    )
-    .await;
-
-    match saved_statistic {
-        Err(elapsed) => tracing::error!("Saving new link click resulted in timeout: {}", elapsed),
-        Ok(Err(err)) => tracing::error!(
-            "Saving a new link click failed with the following error: {}",
-            err
-        ),
-        _ => tracing::debug!(
-            "Persisted new link click for link with id {}, referer {} and user-agent {}",
-            requested_link,
-            referer_header.unwrap_or_default(),
-            user_agent_header.unwrap_or_default()
-        )
-    };
+    .fetch_all(executor)
+    .await
+}
 
-    Ok(
-        Response::builder()
-        .status(StatusCode::TEMPORARY_REDIRECT)
-        .header("location", link.target_url)
-        .header("Cache-Control", DEFAULT_CACHE_CONTROL_HEADER_VALUE)
-        .body(Body::empty())
-        .expect("This response should always be constructable")
+async fn select_link_locale_targets(
+    executor: impl PgExecutor<'_>,
+    link_id: &str
+) -> Result<Vec<LinkLocaleTarget>, sqlx::Error> {
+    sqlx::query_as!(
+        LinkLocaleTarget,
+        "select lang, target_url from link_locale_targets where link_id = $1 order by lang",
+        link_id
     )
+    .fetch_all(executor)
+    .await
 }
 
-
-pub async fn create_link(
-    State(pool): State<PgPool>,
-    Json(new_link): Json<LinkTarget>
-) -> Result<Json<Link>, (StatusCode, String)> {
-    let url = Url::parse(&new_link.target_url)
-    .map_err(|_| (StatusCode::CONFLICT, "url malformed".into()))?
-    .to_string();
-
-    let new_link_id = generate_id();
-
-    let insert_link_timeout = tokio::time::Duration::from_millis(300);
-
-    let new_link = tokio::time::timeout(
-        insert_link_timeout, 
-        sqlx::query_as!(
-            Link,
+async fn select_link_by_id(
+    executor: impl PgExecutor<'_>,
+    config: &Config,
+    requested_link: &str
+) -> Result<Option<Link>, sqlx::Error> {
+    if config.case_insensitive_ids {
+        query_link!(
+            "select",
             r#"
-            with inserted_link as (
-                insert into links(id, target_url)
-                values($1, $2)
-                returning id, target_url
-            ) select id, target_url from inserted_link
+                from links where lower(id) = lower($1)
             "#,
-            &new_link_id,
-            &url
+            requested_link
         )
-        .fetch_one(&pool)
-    )
-    .await
-    .map_err(internal_error)?
-    .map_err(internal_error)?;
-
-    tracing::debug!("Created new link with id {} targeting {}", new_link_id, url);
-
-    Ok(Json(new_link))
-    
+        .fetch_optional(executor)
+        .await
+    } else {
+        query_link!(
+            "select",
+            r#"
+                from links where id = $1
+            "#,
+            requested_link
+        )
+        .fetch_optional(executor)
+        .await
+    }
 }
 
-pub async fn update_link(
-    State(pool): State<PgPool>,
-    Path(link_id): Path<String>,
-    Json(update_link): Json<LinkTarget>
-) -> Result<Json<Link>, (StatusCode, String)> {
-    let url = Url::parse(&update_link.target_url)
-        .map_err(|_| (StatusCode::CONFLICT, "url malformed".into()))?
-        .to_string();
+/// Lets requests for a password protected link through only when they carry
+/// its password, in the `password` query parameter or `X-Link-Password`.
+async fn check_link_password(
+    password_hash: Option<&str>,
+    options: &RedirectOptions,
+    headers: &HeaderMap
+) -> Result<(), ApiError> {
+    let Some(password_hash) = password_hash else {
+        return Ok(());
+    };
 
-    let update_link_timeout = tokio::time::Duration::from_millis(300);
+    let provided_password = options.password.clone().or_else(|| {
+        headers
+            .get("x-link-password")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    });
 
-    let updated_link = tokio::time::timeout(
-        update_link_timeout, 
-        sqlx::query_as!(
-            Link,
-            r#"
-                with updated_link as (
-                    update links set target_url = $1 where id = $2
-                    returning id, target_url
-                ) select id, target_url from updated_link
-            "#,
-            &url,
-            &link_id
-        )
-        .fetch_one(&pool)
-    )
-    .await
-    .map_err(internal_error)?
-    .map_err(internal_error)?;
+    let Some(provided_password) = provided_password else {
+        return Err(ApiError::new(StatusCode::UNAUTHORIZED, "Link requires a password"));
+    };
 
-    tracing::debug!("Updated link with id {} targeting {}", link_id, url);
+    if !password::verify(password_hash.to_string(), provided_password).await? {
+        return Err(ApiError::new(StatusCode::UNAUTHORIZED, "Incorrect link password"));
+    }
 
-    Ok(Json(updated_link))
+    Ok(())
 }
 
-pub async fn get_link_statistic(
-    State(pool): State<PgPool>,
-    Path(link_id): Path<String>,
-) -> Result<Json<Vec<CountedLinkStatistic>>, (StatusCode, String)> {
-    let fetch_statistice_timeout = tokio::time::Duration::from_millis(300);
-
-    let statistics = tokio::time::timeout(
-        fetch_statistice_timeout,
-        sqlx::query_as!(
-            CountedLinkStatistic,
-            r#"
-                select count(*) as amount, referer, user_agent from link_statistics group by link_id, referer, user_agent having link_id = $1
-            "#,
-            &link_id
-        )
-        .fetch_all(&pool)
-    )
+/// Rejects changes to a link created with another API key. Links without an
+/// owner, created before owners were recorded, can be changed by anyone.
+async fn check_owner(
+    pool: &PgPool,
+    config: &Config,
+    link_id: &str,
+    owner: &Owner
+) -> Result<(), ApiError> {
+    let select_timeout = config.db_timeout();
+
+    let link_owner = timed("select_link_owner", tokio::time::timeout(
+        select_timeout,
+        sqlx::query_scalar!("select owner from links where id = $1", link_id)
+            .fetch_optional(pool)
+    ))
     .await
     .map_err(internal_error)?
-    .map_err(internal_error)?;
+    .map_err(database_error)?
+    .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "Not found"))?;
 
-    tracing::debug!("Statistics for link with id {} requested", link_id);
+    if link_owner.is_some_and(|link_owner| link_owner != owner.0) {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, "Link belongs to another API key"));
+    }
 
-    Ok(Json(statistics))
-}
\ No newline at end of file
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::State;
+use axum::response::{IntoResponse, Response};
+use axum::http::StatusCode;
+use axum::Json;
+use sqlx::PgPool;
+use utoipa::ToSchema;
+
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::state::AppState;
+use crate::utils::timed;
+
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct ServiceInfo {
+    pub name: &'static str,
+    pub version: &'static str
+}
+
+#[derive(serde::Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceStatus {
+    pub version: &'static str,
+    pub uptime_seconds: u64,
+    pub pool_size: u32,
+    pub pool_idle: usize,
+    pub pool_max_connections: u32
+}
+
+#[utoipa::path(
+    get,
+    path = "/health",
+    responses(
+        (status = 200, description = "Service is healthy", body = String)
+    )
+)]
+pub async fn health() -> impl IntoResponse {
+    (StatusCode::OK, "Service is healthy")
+}
+
+#[utoipa::path(
+    get,
+    path = "/ready",
+    responses(
+        (status = 200, description = "Service is ready", body = String),
+        (status = 503, description = "Database is unavailable", body = ErrorBody)
+    )
+)]
+pub async fn ready(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let ready_timeout = config.db_timeout();
+
+    let database_check = timed("ready_check", tokio::time::timeout(
+        ready_timeout,
+        sqlx::query("select 1").execute(&pool)
+    ))
+    .await;
+
+    match database_check {
+        Ok(Ok(_)) => Ok((StatusCode::OK, "Service is ready")),
+        Err(elapsed) => {
+            tracing::error!("Readiness check timed out: {}", elapsed);
+            Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "Database is unavailable"))
+        },
+        Ok(Err(err)) => {
+            tracing::error!("Readiness check failed with the following error: {}", err);
+            Err(ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "Database is unavailable"))
+        }
+    }
+}
+
+/// Runtime information for operators. Only reads in-memory pool counters, so
+/// it answers even when the database does not.
+#[utoipa::path(
+    get,
+    path = "/status",
+    responses(
+        (status = 200, description = "Version, uptime and connection pool usage", body = ServiceStatus)
+    )
+)]
+pub async fn service_status(State(state): State<AppState>) -> Json<ServiceStatus> {
+    Json(ServiceStatus {
+        version: env!("CARGO_PKG_VERSION"),
+        uptime_seconds: state.started_at.elapsed().as_secs(),
+        pool_size: state.pool.size(),
+        pool_idle: state.pool.num_idle(),
+        pool_max_connections: state.pool.options().get_max_connections()
+    })
+}
+
+/// Landing response for `/`, a redirect to `HOMEPAGE_URL` when configured.
+#[utoipa::path(
+    get,
+    path = "/",
+    responses(
+        (status = 200, description = "Service name and version", body = ServiceInfo),
+        (status = 302, description = "Redirect to the configured homepage")
+    )
+)]
+pub async fn root(State(config): State<Arc<Config>>) -> Response {
+    match &config.homepage_url {
+        Some(homepage_url) => Response::builder()
+            .status(StatusCode::FOUND)
+            .header("location", homepage_url)
+            .body(Body::empty())
+            .expect("This response should always be constructable"),
+        None => Json(ServiceInfo {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION")
+        })
+        .into_response()
+    }
+}
@@ -0,0 +1,2500 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Cursor;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::{Extension, Path, Query, State};
+use axum::response::{IntoResponse, Response};
+use axum::http::header::{ETAG, IF_NONE_MATCH, LOCATION};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::Json;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use image::{DynamicImage, ImageFormat, Luma};
+use metrics::counter;
+use qrcode::QrCode;
+use rand::rngs::OsRng;
+use rand::Rng;
+use sha3::{Digest, Sha3_256};
+use sqlx::{PgConnection, PgExecutor, PgPool, Postgres, Transaction};
+use url::Url;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::auth::Owner;
+use crate::cache::LinkCache;
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::idempotency::{self, IdempotentRequest};
+use crate::metadata::{LinkMetadata, MetadataFetcher};
+use crate::password;
+use crate::retry::retry_reads;
+use crate::statistics::StatisticsSink;
+use crate::target_check::TargetChecker;
+use crate::utils::{
+    database_error, internal_error, is_referrer_policy, is_relative_target, like_prefix,
+    normalize_relative_target, normalize_target_url, timed, REFERRER_POLICIES
+};
+
+use super::redirect::RedirectOptions;
+use super::{
+    check_link_password, check_owner, query_link, select_link_locale_targets, select_link_variants,
+    Link, LinkLocaleTarget, LinkVariant, Pagination
+};
+
+const MAX_ID_GENERATION_ATTEMPTS: u32 = 5;
+
+/// Argon2 hashes a bulk create computes at once on the blocking pool, each
+/// takes tens of milliseconds and about 19 MiB.
+const MAX_CONCURRENT_PASSWORD_HASHES: usize = 8;
+
+const DEFAULT_LINKS_LIMIT: i64 = 50;
+const MAX_LINKS_LIMIT: i64 = 500;
+
+const MAX_PASSWORD_LENGTH: usize = 256;
+
+const MAX_TAGS: usize = 20;
+const MAX_TAG_LENGTH: usize = 64;
+const MAX_CAMPAIGN_ID_LENGTH: usize = 64;
+const MAX_LINK_VARIANTS: usize = 10;
+const MAX_VARIANT_WEIGHT: i32 = 10_000;
+
+const MAX_LOCALE_TARGETS: usize = 50;
+const MAX_LANGUAGE_SUBTAG_LENGTH: usize = 8;
+
+const DEFAULT_STALE_LINK_DAYS: i64 = 30;
+const MAX_STALE_LINK_DAYS: i64 = 36500;
+
+const DEFAULT_QR_CODE_SIZE: u32 = 256;
+const MIN_QR_CODE_SIZE: u32 = 64;
+const MAX_QR_CODE_SIZE: u32 = 1024;
+
+/// A change of a link's target, as listed by `GET /links/{id}/history`.
+#[derive(serde::Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkHistoryEntry {
+    pub old_target: String,
+    pub new_target: String,
+    pub changed_at: DateTime<Utc>
+}
+
+#[derive(serde::Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedLink {
+    #[serde(flatten)]
+    pub link: Link,
+    pub short_url: String
+}
+
+#[derive(serde::Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkPreview {
+    pub id: String,
+    pub target_url: String
+}
+
+#[derive(serde::Deserialize, serde::Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkTarget {
+    pub target_url: String,
+    pub custom_id: Option<String>,
+    pub permanent: Option<bool>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Expire the link this long from now, e.g. `30m`, `24h` or `7d`, instead
+    /// of at `expiresAt`.
+    pub ttl: Option<String>,
+    pub max_clicks: Option<i64>,
+    pub forward_path: Option<bool>,
+    pub cache_control: Option<String>,
+    pub tags: Option<Vec<String>>,
+    /// Required to follow the link, only its hash is stored.
+    pub password: Option<String>,
+    pub referrer_policy: Option<String>,
+    pub campaign_id: Option<String>
+}
+
+/// Body of `PATCH /links/{id}`, only the fields present are changed. `null`
+/// clears `expiresAt`, `maxClicks`, `cacheControl`, `referrerPolicy` and
+/// `campaignId`, which are `Some(None)` then.
+#[derive(serde::Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateLink {
+    pub target_url: Option<String>,
+    pub permanent: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    #[schema(value_type = Option<DateTime<Utc>>, nullable)]
+    pub expires_at: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    #[schema(value_type = Option<i64>, nullable)]
+    pub max_clicks: Option<Option<i64>>,
+    pub forward_path: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    #[schema(value_type = Option<String>, nullable)]
+    pub cache_control: Option<Option<String>>,
+    pub tags: Option<Vec<String>>,
+    pub password: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    #[schema(value_type = Option<String>, nullable)]
+    pub referrer_policy: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    #[schema(value_type = Option<String>, nullable)]
+    pub campaign_id: Option<Option<String>>
+}
+
+/// Keeps an explicit `null` apart from a missing field, which `#[serde(default)]`
+/// leaves `None`.
+fn deserialize_nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::Deserialize<'de>
+{
+    <Option<T> as serde::Deserialize>::deserialize(deserializer).map(Some)
+}
+
+/// The settings `LinkTarget` and `UpdateLink` share, checked by `validate_link_options`.
+struct LinkOptions<'a> {
+    max_clicks: Option<i64>,
+    cache_control: Option<&'a str>,
+    password: Option<&'a str>,
+    tags: Option<&'a [String]>,
+    referrer_policy: Option<&'a str>,
+    campaign_id: Option<&'a str>
+}
+
+impl LinkTarget {
+    /// `expires_at`, or `ttl` from now.
+    fn resolve_expires_at(&self) -> Result<Option<DateTime<Utc>>, ApiError> {
+        let Some(ttl) = &self.ttl else {
+            return Ok(self.expires_at);
+        };
+
+        if self.expires_at.is_some() {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "only one of expiresAt and ttl can be given"
+            ));
+        }
+
+        humantime::parse_duration(ttl)
+            .ok()
+            .and_then(|ttl| chrono::Duration::from_std(ttl).ok())
+            .and_then(|ttl| Utc::now().checked_add_signed(ttl))
+            .map(Some)
+            .ok_or_else(|| ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "ttl must be a duration like 30m, 24h or 7d"
+            ))
+    }
+
+    fn options(&self) -> LinkOptions<'_> {
+        LinkOptions {
+            max_clicks: self.max_clicks,
+            cache_control: self.cache_control.as_deref(),
+            password: self.password.as_deref(),
+            tags: self.tags.as_deref(),
+            referrer_policy: self.referrer_policy.as_deref(),
+            campaign_id: self.campaign_id.as_deref()
+        }
+    }
+}
+
+impl UpdateLink {
+    fn options(&self) -> LinkOptions<'_> {
+        LinkOptions {
+            max_clicks: self.max_clicks.flatten(),
+            cache_control: self.cache_control.as_ref().and_then(Option::as_deref),
+            password: self.password.as_deref(),
+            tags: self.tags.as_deref(),
+            referrer_policy: self.referrer_policy.as_ref().and_then(Option::as_deref),
+            campaign_id: self.campaign_id.as_ref().and_then(Option::as_deref)
+        }
+    }
+}
+
+#[derive(serde::Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TargetUrl {
+    pub target_url: String
+}
+
+/// Outcome of `POST /links/validate`, `normalized` is set for valid urls and
+/// `reason` for invalid ones.
+#[derive(serde::Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TargetUrlValidation {
+    pub valid: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normalized: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LinkFilter {
+    pub tag: Option<String>
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LinkCountFilter {
+    pub enabled: Option<bool>,
+    pub tag: Option<String>
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StaleLinksOptions {
+    pub days: Option<i64>
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QrCodeOptions {
+    pub size: Option<u32>
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CreateLinkOptions {
+    /// Return a usable link of the caller with the same normalized target
+    /// instead of creating one. Only the target is compared, the other
+    /// settings of the returned link may differ. Not applied to requests
+    /// with a `customId` or `password`.
+    pub dedupe: Option<bool>
+}
+
+/// Filters of `DELETE /links`, at least one is required and links have to
+/// match all given ones.
+#[derive(serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LinkDeleteFilter {
+    pub tag: Option<String>,
+    /// Start of the link id, matched case sensitively.
+    pub prefix: Option<String>
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct DeletedLinks {
+    pub deleted: u64
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct LinkCount {
+    pub count: i64
+}
+
+/// Draws `ID_LENGTH` characters from `ID_ALPHABET`, skipping the rare id that
+/// would collide with a reserved one.
+fn generate_id(config: &Config) -> String {
+    loop {
+        let link_id: String = (0..config.id_length)
+            .map(|_| config.id_alphabet[OsRng.gen_range(0..config.id_alphabet.len())])
+            .collect();
+
+        if !config.is_reserved_id(&link_id) {
+            return link_id;
+        }
+    }
+}
+
+fn validate_custom_id(custom_id: &str, config: &Config) -> Result<(), ApiError> {
+    let is_url_safe = !custom_id.is_empty() && custom_id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    if !is_url_safe {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "custom id may only contain alphanumerics, '-' and '_'"
+        ));
+    }
+
+    if config.is_reserved_id(custom_id) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("custom id {} is reserved", custom_id)
+        ));
+    }
+
+    Ok(())
+}
+
+fn reject_self_referential_url(url: &str, config: &Config) -> Result<(), ApiError> {
+    let Some(base_host) = &config.base_host else {
+        return Ok(());
+    };
+
+    let host = Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_string));
+
+    if host.is_some_and(|host| host.eq_ignore_ascii_case(base_host)) {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "cannot shorten a link to this service"));
+    }
+
+    Ok(())
+}
+
+/// Checks the host of a normalized target against `ALLOWED_TARGET_DOMAINS`
+/// and `BLOCKED_TARGET_DOMAINS`. Path-only targets stay on this service and
+/// are not checked.
+fn reject_disallowed_domain(url: &str, config: &Config) -> Result<(), ApiError> {
+    if is_relative_target(url) {
+        return Ok(());
+    }
+
+    let Some(host) = Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_string)) else {
+        return Ok(());
+    };
+
+    if config.blocked_target_domains.iter().any(|pattern| pattern.matches(&host)) {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            format!("links to {host} are blocked on this service")
+        ));
+    }
+
+    if !config.allowed_target_domains.is_empty()
+        && !config.allowed_target_domains.iter().any(|pattern| pattern.matches(&host))
+    {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            format!("links to {host} are not allowed on this service")
+        ));
+    }
+
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "/links/{id}",
+    params(
+        ("id" = String, Path, description = "Link id"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag of a previous response")
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Link metadata, with its ETag", body = Link),
+        (status = 304, description = "Link unchanged since the ETag in If-None-Match"),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 404, description = "Link not found", body = ErrorBody)
+    )
+)]
+pub async fn get_link(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Path(link_id): Path<String>,
+    headers: HeaderMap
+) -> Result<Response, ApiError> {
+    let select_timeout = config.db_timeout();
+
+    let link = timed("select_link", tokio::time::timeout(
+        select_timeout,
+        query_link!(
+            "select",
+            r#"
+                from links where id = $1
+            "#,
+            &link_id
+        )
+        .fetch_optional(&pool)
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?
+    .ok_or_else(|| "Not found".to_string())
+    .map_err(|err| ApiError::new(StatusCode::NOT_FOUND, err))?;
+
+    let etag = link_etag(&link)?;
+
+    if etag_matches(&headers, &etag) {
+        tracing::debug!("Link with id {} requested, unchanged", link_id);
+
+        return Ok((StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response());
+    }
+
+    tracing::debug!("Link with id {} requested", link_id);
+
+    Ok(([(ETAG, etag)], Json(link)).into_response())
+}
+
+/// Weak ETag over the link's id and target, so it changes whenever
+/// `update_link` points the link somewhere else.
+fn link_etag(link: &Link) -> Result<HeaderValue, ApiError> {
+    let mut hasher = Sha3_256::new();
+    hasher.update(link.id.as_bytes());
+    // Separates the two, `ab` + `c` mustn't hash like `a` + `bc`.
+    hasher.update([0]);
+    hasher.update(link.target_url.as_bytes());
+
+    HeaderValue::from_str(&format!("W/\"{:x}\"", hasher.finalize())).map_err(internal_error)
+}
+
+/// Weak comparison of `If-None-Match` against `etag`, as GET requests use.
+fn etag_matches(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let Some(if_none_match) = headers.get(IF_NONE_MATCH).and_then(|value| value.to_str().ok()) else {
+        return false;
+    };
+
+    let etag = etag.to_str().unwrap_or_default().trim_start_matches("W/");
+
+    if_none_match
+        .split(',')
+        .map(|candidate| candidate.trim())
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+fn validate_links_pagination(pagination: &Pagination) -> Result<(i64, i64), ApiError> {
+    let limit = pagination.limit.unwrap_or(DEFAULT_LINKS_LIMIT);
+    let offset = pagination.offset.unwrap_or(0);
+
+    if !(1..=MAX_LINKS_LIMIT).contains(&limit) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("limit must be between 1 and {}", MAX_LINKS_LIMIT)
+        ));
+    }
+
+    if offset < 0 {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "offset must not be negative"));
+    }
+
+    Ok((limit, offset))
+}
+
+/// Lists links, optionally only those carrying `tag`.
+#[utoipa::path(
+    get,
+    path = "/links",
+    params(LinkFilter, Pagination),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Links ordered by id", body = Vec<Link>),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody)
+    )
+)]
+pub async fn list_links(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(filter): Query<LinkFilter>,
+    Query(pagination): Query<Pagination>
+) -> Result<Json<Vec<Link>>, ApiError> {
+    let (limit, offset) = validate_links_pagination(&pagination)?;
+
+    let select_timeout = config.db_timeout();
+
+    let links = timed("select_links", tokio::time::timeout(
+        select_timeout,
+        query_link!(
+            "select",
+            r#"
+                from links
+                where $1::text is null or tags @> array[$1::text]
+                order by id limit $2 offset $3
+            "#,
+            filter.tag,
+            limit,
+            offset
+        )
+        .fetch_all(&pool)
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?;
+
+    tracing::debug!("{} links with tag {:?} requested", links.len(), filter.tag);
+
+    Ok(Json(links))
+}
+
+/// Lists the links created with the API key of the request.
+#[utoipa::path(
+    get,
+    path = "/links/mine",
+    params(Pagination),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Links of the caller ordered by id", body = Vec<Link>),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody)
+    )
+)]
+pub async fn list_owned_links(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Extension(owner): Extension<Owner>,
+    Query(pagination): Query<Pagination>
+) -> Result<Json<Vec<Link>>, ApiError> {
+    let (limit, offset) = validate_links_pagination(&pagination)?;
+
+    let select_timeout = config.db_timeout();
+
+    let links = timed("select_owned_links", tokio::time::timeout(
+        select_timeout,
+        query_link!(
+            "select",
+            r#"
+                from links
+                where owner = $1
+                order by id limit $2 offset $3
+            "#,
+            owner.0,
+            limit,
+            offset
+        )
+        .fetch_all(&pool)
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?;
+
+    tracing::debug!("{} links of the caller requested", links.len());
+
+    Ok(Json(links))
+}
+
+/// Number of links, optionally only those with the given `enabled` flag or
+/// carrying `tag`.
+#[utoipa::path(
+    get,
+    path = "/links/count",
+    params(LinkCountFilter),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Number of matching links", body = LinkCount),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody)
+    )
+)]
+pub async fn count_links(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(filter): Query<LinkCountFilter>
+) -> Result<Json<LinkCount>, ApiError> {
+    let select_timeout = config.db_timeout();
+
+    let count = timed("count_links", tokio::time::timeout(
+        select_timeout,
+        sqlx::query_scalar!(
+            r#"
+                select count(*) as "count!" from links
+                where ($1::boolean is null or enabled = $1)
+                    and ($2::text is null or tags @> array[$2::text])
+            "#,
+            filter.enabled,
+            filter.tag
+        )
+        .fetch_one(&pool)
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?;
+
+    tracing::debug!(
+        "Link count with enabled {:?} and tag {:?} requested",
+        filter.enabled,
+        filter.tag
+    );
+
+    Ok(Json(LinkCount { count }))
+}
+
+/// Lists the links grouped under a campaign.
+#[utoipa::path(
+    get,
+    path = "/campaigns/{id}/links",
+    params(("id" = String, Path, description = "Campaign id"), Pagination),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Links of the campaign ordered by id", body = Vec<Link>),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody)
+    )
+)]
+pub async fn list_campaign_links(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Path(campaign_id): Path<String>,
+    Query(pagination): Query<Pagination>
+) -> Result<Json<Vec<Link>>, ApiError> {
+    let (limit, offset) = validate_links_pagination(&pagination)?;
+
+    let select_timeout = config.db_timeout();
+
+    let links = timed("select_campaign_links", tokio::time::timeout(
+        select_timeout,
+        query_link!(
+            "select",
+            r#"
+                from links
+                where campaign_id = $1
+                order by id limit $2 offset $3
+            "#,
+            &campaign_id,
+            limit,
+            offset
+        )
+        .fetch_all(&pool)
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?;
+
+    tracing::debug!("{} links of campaign {} requested", links.len(), campaign_id);
+
+    Ok(Json(links))
+}
+
+/// Resolves a link like `redirect` does, but without recording a click.
+#[utoipa::path(
+    get,
+    path = "/links/{id}/preview",
+    params(("id" = String, Path, description = "Link id"), RedirectOptions),
+    responses(
+        (status = 200, description = "Link target, without recording a click", body = LinkPreview),
+        (status = 401, description = "Link password missing or incorrect", body = ErrorBody),
+        (status = 404, description = "Link not found", body = ErrorBody)
+    )
+)]
+pub async fn preview_link(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Path(link_id): Path<String>,
+    Query(options): Query<RedirectOptions>,
+    headers: HeaderMap
+) -> Result<Json<LinkPreview>, ApiError> {
+    let select_timeout = config.db_timeout();
+
+    let link = timed("select_link", tokio::time::timeout(
+        select_timeout,
+        sqlx::query!(
+            "select id, target_url, password_hash from links where id = $1",
+            &link_id
+        )
+        .fetch_optional(&pool)
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?
+    .ok_or_else(|| "Not found".to_string())
+    .map_err(|err| ApiError::new(StatusCode::NOT_FOUND, err))?;
+
+    check_link_password(link.password_hash.as_deref(), &options, &headers).await?;
+
+    tracing::debug!("Preview for link with id {} requested", link_id);
+
+    Ok(Json(LinkPreview { id: link.id, target_url: link.target_url }))
+}
+
+/// Open Graph title, description and image of the link's target, for chat
+/// and social embeds. It makes this service fetch the target, so it needs an
+/// API key, and protected links need their password like `preview_link`.
+#[utoipa::path(
+    get,
+    path = "/links/{id}/metadata",
+    params(("id" = String, Path, description = "Link id"), RedirectOptions),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Open Graph tags found on the target, with fetchError when it could not be read", body = LinkMetadata),
+        (status = 401, description = "Missing or invalid API key, or link password missing or incorrect", body = ErrorBody),
+        (status = 404, description = "Link not found", body = ErrorBody)
+    )
+)]
+pub async fn get_link_metadata(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    State(metadata_fetcher): State<MetadataFetcher>,
+    Path(link_id): Path<String>,
+    Query(options): Query<RedirectOptions>,
+    headers: HeaderMap
+) -> Result<Json<LinkMetadata>, ApiError> {
+    let select_timeout = config.db_timeout();
+
+    let link = timed("select_link", tokio::time::timeout(
+        select_timeout,
+        sqlx::query!(
+            "select id, target_url, password_hash from links where id = $1",
+            &link_id
+        )
+        .fetch_optional(&pool)
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?
+    .ok_or_else(|| "Not found".to_string())
+    .map_err(|err| ApiError::new(StatusCode::NOT_FOUND, err))?;
+
+    check_link_password(link.password_hash.as_deref(), &options, &headers).await?;
+
+    let target_url = if is_relative_target(&link.target_url) {
+        config.base_url.join(&link.target_url).map_err(internal_error)?.to_string()
+    } else {
+        link.target_url
+    };
+
+    let metadata = metadata_fetcher.fetch(&link.id, &target_url).await;
+
+    tracing::debug!("Metadata for link with id {} requested", link_id);
+
+    Ok(Json(metadata))
+}
+
+fn validate_target_url(target_url: &str, config: &Config) -> Result<String, ApiError> {
+    if target_url.len() > config.max_target_url_length {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("url must not be longer than {} characters", config.max_target_url_length)
+        ));
+    }
+
+    let url = if is_relative_target(target_url.trim()) {
+        if !config.allow_relative_targets {
+            return Err(ApiError::new(StatusCode::BAD_REQUEST, "path-only targets are not enabled"));
+        }
+
+        normalize_relative_target(target_url, &config.base_url)
+    } else {
+        normalize_target_url(target_url)
+    }
+    .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, err.to_string()))?;
+
+    reject_self_referential_url(&url, config)?;
+    reject_disallowed_domain(&url, config)?;
+
+    Ok(url)
+}
+
+/// Runs the target url checks of `create_link` without creating a link.
+#[utoipa::path(
+    post,
+    path = "/links/validate",
+    request_body = TargetUrl,
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Whether the url can be shortened", body = TargetUrlValidation),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 413, description = "Request body too large")
+    )
+)]
+pub async fn validate_link(
+    State(config): State<Arc<Config>>,
+    Json(target): Json<TargetUrl>
+) -> Json<TargetUrlValidation> {
+    let validation = match validate_target_url(&target.target_url, &config) {
+        Ok(normalized) => TargetUrlValidation {
+            valid: true,
+            normalized: Some(normalized),
+            reason: None
+        },
+        Err(err) => TargetUrlValidation {
+            valid: false,
+            normalized: None,
+            reason: Some(err.message)
+        }
+    };
+
+    tracing::debug!("Validated target url {}, valid: {}", target.target_url, validation.valid);
+
+    Json(validation)
+}
+
+fn validate_link_options(link: &LinkOptions) -> Result<(), ApiError> {
+    if link.max_clicks.is_some_and(|max_clicks| max_clicks < 1) {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "maxClicks must be at least 1"));
+    }
+
+    let invalid_cache_control = link
+        .cache_control
+        .is_some_and(|cache_control| HeaderValue::from_str(cache_control).is_err());
+
+    if invalid_cache_control {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "cacheControl must be a valid header value"
+        ));
+    }
+
+    let invalid_password = link
+        .password
+        .is_some_and(|password| password.is_empty() || password.len() > MAX_PASSWORD_LENGTH);
+
+    if invalid_password {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("password must be between 1 and {} characters", MAX_PASSWORD_LENGTH)
+        ));
+    }
+
+    let invalid_referrer_policy = link
+        .referrer_policy
+        .is_some_and(|referrer_policy| !is_referrer_policy(referrer_policy));
+
+    if invalid_referrer_policy {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("referrerPolicy must be one of {}", REFERRER_POLICIES.join(", "))
+        ));
+    }
+
+    let invalid_campaign_id = link.campaign_id.is_some_and(|campaign_id| {
+        campaign_id.is_empty()
+            || campaign_id.len() > MAX_CAMPAIGN_ID_LENGTH
+            || !campaign_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    });
+
+    if invalid_campaign_id {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "campaignId must be between 1 and {} alphanumerics, '-' and '_'",
+                MAX_CAMPAIGN_ID_LENGTH
+            )
+        ));
+    }
+
+    if let Some(tags) = link.tags {
+        if tags.len() > MAX_TAGS {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!("a link can have at most {} tags", MAX_TAGS)
+            ));
+        }
+
+        if tags.iter().any(|tag| tag.is_empty() || tag.len() > MAX_TAG_LENGTH) {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!("tags must be between 1 and {} characters", MAX_TAG_LENGTH)
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Rejects expirations that are already past or further ahead than
+/// `MAX_EXPIRY_DAYS`. A missing expiry means the link never expires on create
+/// and is left unchanged on update, so it only fails when `required`.
+fn validate_expires_at(
+    expires_at: Option<DateTime<Utc>>,
+    required: bool,
+    config: &Config
+) -> Result<(), ApiError> {
+    let allowed_range = match config.max_expiry_days {
+        Some(max_expiry_days) => format!("between now and {} days from now", max_expiry_days),
+        None => "in the future".to_string()
+    };
+
+    let Some(expires_at) = expires_at else {
+        if required {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!("expiresAt is required and must be {}", allowed_range)
+            ));
+        }
+
+        return Ok(());
+    };
+
+    let now = Utc::now();
+    let too_late = config
+        .max_expiry_days
+        .is_some_and(|max_expiry_days| expires_at > now + chrono::Duration::days(max_expiry_days));
+
+    if expires_at <= now || too_late {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("expiresAt must be {}", allowed_range)
+        ));
+    }
+
+    Ok(())
+}
+
+fn new_link_id(new_link: &LinkTarget, config: &Config) -> Result<String, ApiError> {
+    match &new_link.custom_id {
+        Some(custom_id) => {
+            validate_custom_id(custom_id, config)?;
+            Ok(custom_id.clone())
+        },
+        None => Ok(generate_id(config))
+    }
+}
+
+async fn insert_link<'e, E>(
+    executor: E,
+    link_id: &str,
+    url: &str,
+    new_link: &LinkTarget,
+    password_hash: Option<&str>,
+    owner: &Owner
+) -> Result<Link, sqlx::Error>
+where E: PgExecutor<'e>,
+{
+    query_link!(
+        r#"
+        with inserted_link as (
+            insert into links(
+                id, target_url, permanent, expires_at, max_clicks, forward_path, cache_control, tags,
+                password_hash, owner, referrer_policy, campaign_id
+            )
+            values($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+            returning
+        "#,
+        r#"
+        ) select * from inserted_link
+        "#,
+        link_id,
+        url,
+        new_link.permanent.unwrap_or(false),
+        new_link.expires_at,
+        new_link.max_clicks,
+        new_link.forward_path.unwrap_or(false),
+        new_link.cache_control,
+        new_link.tags.as_deref().unwrap_or_default(),
+        password_hash,
+        owner.0,
+        new_link.referrer_policy,
+        new_link.campaign_id
+    )
+    .fetch_one(executor)
+    .await
+}
+
+/// Inserts all `new_links` in one statement, each with the id and normalized
+/// url at the same index of `prepared_links` and the hash at that index of
+/// `password_hashes`. Links whose id is taken are skipped rather than failing
+/// the statement, so the caller can tell which ones those were.
+async fn insert_links<'e, E>(
+    executor: E,
+    prepared_links: &[(String, String)],
+    new_links: &[LinkTarget],
+    password_hashes: &[Option<String>],
+    owner: &Owner
+) -> Result<Vec<Link>, sqlx::Error>
+where E: PgExecutor<'e>,
+{
+    let (link_ids, urls): (Vec<_>, Vec<_>) = prepared_links.iter().cloned().unzip();
+
+    // Arrays of arrays must be rectangular, so each tag list goes in as json.
+    let tags: Vec<serde_json::Value> = new_links
+        .iter()
+        .map(|new_link| serde_json::json!(new_link.tags.as_deref().unwrap_or_default()))
+        .collect();
+
+    query_link!(
+        r#"
+        with inserted_links as (
+            insert into links(
+                id, target_url, permanent, expires_at, max_clicks, forward_path, cache_control, tags,
+                password_hash, owner, referrer_policy, campaign_id
+            )
+            select
+                new_links.id, new_links.target_url, new_links.permanent, new_links.expires_at,
+                new_links.max_clicks, new_links.forward_path, new_links.cache_control,
+                array(select jsonb_array_elements_text(new_links.tags)), new_links.password_hash,
+                $10, new_links.referrer_policy, new_links.campaign_id
+            from unnest(
+                $1::text[], $2::text[], $3::bool[], $4::timestamptz[], $5::int8[], $6::bool[],
+                $7::text[], $8::jsonb[], $9::text[], $11::text[], $12::text[]
+            ) as new_links(
+                id, target_url, permanent, expires_at, max_clicks, forward_path, cache_control,
+                tags, password_hash, referrer_policy, campaign_id
+            )
+            on conflict do nothing
+            returning
+        "#,
+        r#"
+        ) select * from inserted_links
+        "#,
+        &link_ids,
+        &urls,
+        &new_links.iter().map(|new_link| new_link.permanent.unwrap_or(false)).collect::<Vec<_>>(),
+        &new_links.iter().map(|new_link| new_link.expires_at).collect::<Vec<_>>() as _,
+        &new_links.iter().map(|new_link| new_link.max_clicks).collect::<Vec<_>>() as _,
+        &new_links.iter().map(|new_link| new_link.forward_path.unwrap_or(false)).collect::<Vec<_>>(),
+        &new_links.iter().map(|new_link| new_link.cache_control.clone()).collect::<Vec<_>>() as _,
+        &tags,
+        password_hashes as _,
+        owner.0,
+        &new_links.iter().map(|new_link| new_link.referrer_policy.clone()).collect::<Vec<_>>() as _,
+        &new_links.iter().map(|new_link| new_link.campaign_id.clone()).collect::<Vec<_>>() as _
+    )
+    .fetch_all(executor)
+    .await
+}
+
+/// Oldest link of `owner` targeting exactly `url` that still redirects and has
+/// no password. Targets are compared after normalization, so urls differing in
+/// host casing, a default port or a trailing empty fragment match, while a
+/// different path casing, query order or trailing slash in the path do not.
+async fn find_duplicate_link(
+    pool: &PgPool,
+    config: &Config,
+    url: &str,
+    owner: &Owner
+) -> Result<Option<Link>, ApiError> {
+    let select_timeout = config.db_timeout();
+
+    timed("select_duplicate_link", tokio::time::timeout(
+        select_timeout,
+        query_link!(
+            "select",
+            r#"
+                from links
+                where target_url = $1 and owner = $2
+                    and enabled and password_hash is null
+                    and (expires_at is null or expires_at > now())
+                    and (max_clicks is null or limited_clicks < max_clicks)
+                order by id limit 1
+            "#,
+            url,
+            owner.0
+        )
+        .fetch_optional(pool)
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)
+}
+
+fn link_id_taken_error(link_id: &str) -> ApiError {
+    ApiError::new(StatusCode::CONFLICT, format!("link with id {} already exists", link_id))
+}
+
+/// Lower cased ids of `link_ids` that an existing link has in any casing. With
+/// `CASE_INSENSITIVE_IDS` such ids would redirect to the existing link, and
+/// the index on `lower(id)` doesn't prevent them, so creating links checks
+/// with this first. Each id stays locked until the transaction ends, which
+/// keeps concurrent requests from both creating it.
+async fn lock_taken_case_insensitive_ids(
+    connection: &mut PgConnection,
+    link_ids: &[String]
+) -> Result<HashSet<String>, sqlx::Error> {
+    let lower_ids: Vec<String> = link_ids.iter().map(|link_id| link_id.to_lowercase()).collect();
+
+    // Locked in order, so requests overlapping in several ids can't deadlock.
+    sqlx::query(
+        r#"
+            select pg_advisory_xact_lock(hashtext(lower_id))
+            from (select distinct lower_id from unnest($1::text[]) as lower_id order by lower_id) as lower_ids
+        "#
+    )
+    .bind(&lower_ids)
+    .execute(&mut *connection)
+    .await?;
+
+    let taken_ids = sqlx::query_scalar!(
+        r#"select lower(id) as "lower_id!" from links where lower(id) = any($1)"#,
+        &lower_ids
+    )
+    .fetch_all(&mut *connection)
+    .await?;
+
+    Ok(taken_ids.into_iter().collect())
+}
+
+/// How the transaction inserting the link of `create_link` ended.
+enum LinkInsert {
+    Created(Box<Link>),
+    IdTaken,
+    IdempotencyKeyInUse
+}
+
+#[utoipa::path(
+    post,
+    path = "/create",
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Makes retries return the link created by the first request"),
+        CreateLinkOptions
+    ),
+    request_body = LinkTarget,
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Existing link with the same target returned because of dedupe", body = CreatedLink),
+        (status = 201, description = "Link created, its resource url in the Location header", body = CreatedLink),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 403, description = "Target domain is not allowed", body = ErrorBody),
+        (status = 409, description = "Custom id already exists or idempotency key was reused", body = ErrorBody),
+        (status = 413, description = "Request body too large"),
+        (status = 429, description = "Rate limit exceeded", body = ErrorBody)
+    )
+)]
+pub async fn create_link(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    State(target_checker): State<Option<TargetChecker>>,
+    Extension(owner): Extension<Owner>,
+    Query(options): Query<CreateLinkOptions>,
+    headers: HeaderMap,
+    Json(mut new_link): Json<LinkTarget>
+) -> Result<Response, ApiError> {
+    let url = validate_target_url(&new_link.target_url, &config)?;
+    validate_link_options(&new_link.options())?;
+    let expires_at = new_link.resolve_expires_at()?;
+    validate_expires_at(expires_at, config.require_expiry, &config)?;
+
+    // Hashed as sent, a retry with the same `ttl` resolves to another time.
+    let idempotent_request = IdempotentRequest::from_headers(&headers, &new_link)?;
+    new_link.expires_at = expires_at;
+
+    if let Some(request) = &idempotent_request {
+        if let Some(link) = idempotency::find_link(&pool, &config, request).await? {
+            tracing::debug!(
+                "Returning link with id {} created earlier for idempotency key {}",
+                link.id,
+                request.key
+            );
+
+            return created_link_response(link, &config);
+        }
+    }
+
+    let dedupe = options.dedupe.unwrap_or(false)
+        && new_link.custom_id.is_none()
+        && new_link.password.is_none();
+
+    if dedupe {
+        if let Some(link) = find_duplicate_link(&pool, &config, &url, &owner).await? {
+            tracing::debug!("Returning existing link with id {} targeting {}", link.id, url);
+
+            return Ok(Json(CreatedLink {
+                short_url: config.short_url(&link.id),
+                link
+            })
+            .into_response());
+        }
+    }
+
+    // Path-only targets point at this deployment, there is nothing to verify.
+    if let Some(target_checker) = target_checker.filter(|_| !is_relative_target(&url)) {
+        target_checker.check(&url).await?;
+    }
+
+    let password_hash = match &new_link.password {
+        Some(password) => Some(password::hash(password.clone()).await?),
+        None => None
+    };
+
+    let insert_link_timeout = config.db_timeout();
+
+    let mut attempts = 1;
+
+    let created_link = loop {
+        let new_link_id = new_link_id(&new_link, &config)?;
+
+        let inserted_link = timed("insert_link", tokio::time::timeout(
+            insert_link_timeout, 
+            async {
+                let mut transaction = pool.begin().await?;
+
+                if config.case_insensitive_ids {
+                    let taken_ids = lock_taken_case_insensitive_ids(
+                        &mut transaction,
+                        std::slice::from_ref(&new_link_id)
+                    )
+                    .await?;
+
+                    if !taken_ids.is_empty() {
+                        return Ok(LinkInsert::IdTaken);
+                    }
+                }
+
+                let created_link = insert_link(
+                    &mut *transaction,
+                    &new_link_id,
+                    &url,
+                    &new_link,
+                    password_hash.as_deref(),
+                    &owner
+                )
+                .await?;
+
+                if let Some(request) = &idempotent_request {
+                    if !idempotency::store(&mut *transaction, &config, request, &created_link.id).await? {
+                        return Ok(LinkInsert::IdempotencyKeyInUse);
+                    }
+                }
+
+                transaction.commit().await?;
+
+                Ok::<LinkInsert, sqlx::Error>(LinkInsert::Created(Box::new(created_link)))
+            }
+        ))
+        .await
+        .map_err(internal_error)?;
+
+        match inserted_link {
+            Ok(LinkInsert::Created(created_link)) => break *created_link,
+            Ok(LinkInsert::IdempotencyKeyInUse) => return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                "idempotency key is already used by a request in progress"
+            )),
+            Ok(LinkInsert::IdTaken) => {},
+            Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {},
+            Err(err) => return Err(database_error(err))
+        }
+
+        if new_link.custom_id.is_some() {
+            return Err(link_id_taken_error(&new_link_id));
+        }
+
+        counter!("link_id_collisions_count").increment(1);
+
+        if attempts == MAX_ID_GENERATION_ATTEMPTS {
+            tracing::error!("Could not generate a unique link id after {} attempts", attempts);
+
+            return Err(ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "could not generate a unique link id"
+            ));
+        }
+
+        tracing::warn!("Generated link id {} already exists, retrying", new_link_id);
+        attempts += 1;
+    };
+
+    if attempts > 1 {
+        tracing::warn!("Generated a unique link id after {} attempts", attempts);
+    }
+
+    tracing::debug!("Created new link with id {} targeting {}", created_link.id, url);
+    counter!("link_creations_count").increment(1);
+
+    created_link_response(created_link, &config)
+}
+
+/// 201 for a new link, with the url of its `GET /links/{id}` resource as `Location`.
+fn created_link_response(link: Link, config: &Config) -> Result<Response, ApiError> {
+    let location = HeaderValue::from_str(&config.link_url(&link.id)).map_err(internal_error)?;
+
+    let created_link = CreatedLink {
+        short_url: config.short_url(&link.id),
+        link
+    };
+
+    Ok((StatusCode::CREATED, [(LOCATION, location)], Json(created_link)).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/links/bulk",
+    request_body = Vec<LinkTarget>,
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "All links created", body = Vec<CreatedLink>),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 409, description = "Custom id already exists", body = ErrorBody),
+        (status = 413, description = "Request body too large"),
+        (status = 429, description = "Rate limit exceeded", body = ErrorBody)
+    )
+)]
+pub async fn create_links_bulk(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Extension(owner): Extension<Owner>,
+    Json(mut new_links): Json<Vec<LinkTarget>>
+) -> Result<Json<Vec<CreatedLink>>, ApiError> {
+    if new_links.len() > config.bulk_create_max_links {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("at most {} links can be created at once", config.bulk_create_max_links)
+        ));
+    }
+
+    let mut prepared_links = Vec::with_capacity(new_links.len());
+    let mut invalid_links = Vec::new();
+
+    for (index, new_link) in new_links.iter_mut().enumerate() {
+        let prepared_link = validate_target_url(&new_link.target_url, &config)
+            .and_then(|url| {
+                validate_link_options(&new_link.options())?;
+                new_link.expires_at = new_link.resolve_expires_at()?;
+                validate_expires_at(new_link.expires_at, config.require_expiry, &config)?;
+                Ok((new_link_id(new_link, &config)?, url))
+            });
+
+        match prepared_link {
+            Ok(prepared_link) => prepared_links.push(prepared_link),
+            Err(err) => invalid_links.push(format!("index {}: {}", index, err.message))
+        }
+    }
+
+    if !invalid_links.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("invalid links: {}", invalid_links.join("; "))
+        ));
+    }
+
+    let passwords: Vec<Option<String>> =
+        new_links.iter().map(|new_link| new_link.password.clone()).collect();
+    let password_hashes: Vec<Option<String>> = stream::iter(passwords)
+        .map(|password| async move {
+            match password {
+                Some(password) => password::hash(password).await.map(Some),
+                None => Ok(None)
+            }
+        })
+        .buffered(MAX_CONCURRENT_PASSWORD_HASHES)
+        .try_collect()
+        .await?;
+
+    let created_links = timed("insert_links", tokio::time::timeout(
+        config.bulk_db_timeout(),
+        async {
+            let mut transaction = pool.begin().await.map_err(database_error)?;
+
+            if config.case_insensitive_ids {
+                let link_ids: Vec<String> =
+                    prepared_links.iter().map(|(link_id, _)| link_id.clone()).collect();
+                let mut taken_ids = lock_taken_case_insensitive_ids(&mut transaction, &link_ids)
+                    .await
+                    .map_err(database_error)?;
+
+                // In request order, so an id only differing in case from one
+                // earlier in the request is the one reported.
+                for link_id in &link_ids {
+                    if !taken_ids.insert(link_id.to_lowercase()) {
+                        return Err(link_id_taken_error(link_id));
+                    }
+                }
+            }
+
+            let inserted_links =
+                insert_links(&mut *transaction, &prepared_links, &new_links, &password_hashes, &owner)
+                    .await
+                    .map_err(database_error)?;
+
+            let mut inserted_links: HashMap<String, Link> = inserted_links
+                .into_iter()
+                .map(|link| (link.id.clone(), link))
+                .collect();
+
+            // In request order, an id missing from the inserted links was taken,
+            // by an existing link or one earlier in the request.
+            let created_links = prepared_links
+                .iter()
+                .map(|(link_id, _)| {
+                    inserted_links.remove(link_id).ok_or_else(|| link_id_taken_error(link_id))
+                })
+                .collect::<Result<Vec<Link>, ApiError>>()?;
+
+            transaction.commit().await.map_err(database_error)?;
+
+            Ok::<Vec<Link>, ApiError>(created_links)
+        }
+    ))
+    .await
+    .map_err(internal_error)??;
+
+    tracing::debug!("Created {} new links in bulk", created_links.len());
+    counter!("link_creations_count").increment(created_links.len() as u64);
+
+    Ok(Json(
+        created_links
+            .into_iter()
+            .map(|link| CreatedLink {
+                short_url: config.short_url(&link.id),
+                link
+            })
+            .collect()
+    ))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/{id}",
+    params(("id" = String, Path, description = "Link id")),
+    request_body = LinkTarget,
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Link updated, with its new ETag", body = Link),
+        (status = 400, description = "Invalid input or a customId, which can't be changed", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 403, description = "Link belongs to another API key or target domain is not allowed", body = ErrorBody),
+        (status = 404, description = "Link not found", body = ErrorBody),
+        (status = 413, description = "Request body too large"),
+        (status = 429, description = "Rate limit exceeded", body = ErrorBody)
+    )
+)]
+pub async fn update_link(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    State(cache): State<LinkCache>,
+    Extension(owner): Extension<Owner>,
+    Path(link_id): Path<String>,
+    Json(mut update_link): Json<LinkTarget>
+) -> Result<Response, ApiError> {
+    let url = validate_target_url(&update_link.target_url, &config)?;
+    validate_link_options(&update_link.options())?;
+    update_link.expires_at = update_link.resolve_expires_at()?;
+    validate_expires_at(update_link.expires_at, false, &config)?;
+
+    // Renaming would break every short url already handed out.
+    if update_link.custom_id.is_some() {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "the id of an existing link can't be changed"
+        ));
+    }
+
+    let password_hash = match &update_link.password {
+        Some(password) => Some(password::hash(password.clone()).await?),
+        None => None
+    };
+
+    check_owner(&pool, &config, &link_id, &owner).await?;
+
+    let update_link_timeout = config.db_timeout();
+
+    let updated_link = timed("update_link", tokio::time::timeout(
+        update_link_timeout,
+        async {
+            let mut transaction = pool.begin().await?;
+
+            let old_target = sqlx::query_scalar!(
+                "select target_url from links where id = $1 for update",
+                &link_id
+            )
+            .fetch_optional(&mut *transaction)
+            .await?;
+
+            let Some(old_target) = old_target else {
+                return Ok(None);
+            };
+
+            let updated_link = query_link!(
+                r#"
+                    with updated_link as (
+                        update links set
+                            target_url = $1,
+                            permanent = coalesce($3, permanent),
+                            expires_at = coalesce($4, expires_at),
+                            max_clicks = coalesce($5, max_clicks),
+                            forward_path = coalesce($6, forward_path),
+                            cache_control = coalesce($7, cache_control),
+                            tags = coalesce($8, tags),
+                            password_hash = coalesce($9, password_hash),
+                            referrer_policy = coalesce($10, referrer_policy),
+                            campaign_id = coalesce($11, campaign_id)
+                        where id = $2
+                        returning
+                "#,
+                r#"
+                    ) select * from updated_link
+                "#,
+                &url,
+                &link_id,
+                update_link.permanent,
+                update_link.expires_at,
+                update_link.max_clicks,
+                update_link.forward_path,
+                update_link.cache_control,
+                update_link.tags.as_deref(),
+                password_hash,
+                update_link.referrer_policy,
+                update_link.campaign_id
+            )
+            .fetch_one(&mut *transaction)
+            .await?;
+
+            record_target_change(
+                &mut transaction,
+                &config,
+                &link_id,
+                &old_target,
+                &updated_link.target_url
+            )
+            .await?;
+
+            transaction.commit().await?;
+
+            Ok(Some(updated_link))
+        }
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?
+    .ok_or_else(|| "Not found".to_string())
+    .map_err(|err| ApiError::new(StatusCode::NOT_FOUND, err))?;
+
+    cache.invalidate(&link_id).await;
+
+    tracing::debug!("Updated link with id {} targeting {}", link_id, url);
+
+    Ok(([(ETAG, link_etag(&updated_link)?)], Json(updated_link)).into_response())
+}
+
+/// Appends a target change to `link_history` and drops the entries of the
+/// link beyond the newest `LINK_HISTORY_MAX_ENTRIES`. Updates that keep the
+/// target are not recorded.
+async fn record_target_change(
+    transaction: &mut Transaction<'_, Postgres>,
+    config: &Config,
+    link_id: &str,
+    old_target: &str,
+    new_target: &str
+) -> Result<(), sqlx::Error> {
+    if old_target == new_target {
+        return Ok(());
+    }
+
+    sqlx::query!(
+        "insert into link_history(link_id, old_target, new_target) values($1, $2, $3)",
+        link_id,
+        old_target,
+        new_target
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    sqlx::query!(
+        r#"
+            delete from link_history
+            where link_id = $1 and id not in (
+                select id from link_history
+                where link_id = $1
+                order by changed_at desc, id desc
+                limit $2
+            )
+        "#,
+        link_id,
+        config.link_history_max_entries
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Lists the target changes of a link, newest first.
+#[utoipa::path(
+    get,
+    path = "/links/{id}/history",
+    params(("id" = String, Path, description = "Link id")),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Target changes of the link, newest first", body = Vec<LinkHistoryEntry>),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 404, description = "Link not found", body = ErrorBody)
+    )
+)]
+pub async fn get_link_history(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Path(link_id): Path<String>
+) -> Result<Json<Vec<LinkHistoryEntry>>, ApiError> {
+    let select_timeout = config.db_timeout();
+
+    let history = timed("select_link_history", tokio::time::timeout(
+        select_timeout,
+        retry_reads("select_link_history", config.db_read_retries, || async {
+            let link = sqlx::query_scalar!("select id from links where id = $1", &link_id)
+                .fetch_optional(&pool)
+                .await?;
+
+            if link.is_none() {
+                return Ok(None);
+            }
+
+            sqlx::query_as!(
+                LinkHistoryEntry,
+                r#"
+                    select old_target, new_target, changed_at from link_history
+                    where link_id = $1
+                    order by changed_at desc, id desc
+                "#,
+                &link_id
+            )
+            .fetch_all(&pool)
+            .await
+            .map(Some)
+        })
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?
+    .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "Not found"))?;
+
+    tracing::debug!("History of link with id {} requested", link_id);
+
+    Ok(Json(history))
+}
+
+#[utoipa::path(
+    get,
+    path = "/links/{id}/variants",
+    params(("id" = String, Path, description = "Link id")),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Variants of the link, empty when it has a single target", body = Vec<LinkVariant>),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 404, description = "Link not found", body = ErrorBody)
+    )
+)]
+pub async fn get_link_variants(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Path(link_id): Path<String>
+) -> Result<Json<Vec<LinkVariant>>, ApiError> {
+    let select_timeout = config.db_timeout();
+
+    let variants = timed("select_link_variants", tokio::time::timeout(
+        select_timeout,
+        async {
+            let link = sqlx::query_scalar!("select id from links where id = $1", &link_id)
+                .fetch_optional(&pool)
+                .await?;
+
+            match link {
+                Some(_) => select_link_variants(&pool, &link_id).await.map(Some),
+                None => Ok(None)
+            }
+        }
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?
+    .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "Not found"))?;
+
+    tracing::debug!("Variants of link with id {} requested", link_id);
+
+    Ok(Json(variants))
+}
+
+/// Replaces all variants of a link. While a link has variants `redirect`
+/// serves one of them instead of its `targetUrl`, an empty list goes back to
+/// the single target.
+#[utoipa::path(
+    put,
+    path = "/links/{id}/variants",
+    params(("id" = String, Path, description = "Link id")),
+    request_body = Vec<LinkVariant>,
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Variants stored, with normalized targets", body = Vec<LinkVariant>),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 403, description = "Link belongs to another API key or target domain is not allowed", body = ErrorBody),
+        (status = 404, description = "Link not found", body = ErrorBody),
+        (status = 413, description = "Request body too large"),
+        (status = 429, description = "Rate limit exceeded", body = ErrorBody)
+    )
+)]
+pub async fn replace_link_variants(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    State(cache): State<LinkCache>,
+    Extension(owner): Extension<Owner>,
+    Path(link_id): Path<String>,
+    Json(new_variants): Json<Vec<LinkVariant>>
+) -> Result<Json<Vec<LinkVariant>>, ApiError> {
+    if new_variants.len() > MAX_LINK_VARIANTS {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("a link can have at most {} variants", MAX_LINK_VARIANTS)
+        ));
+    }
+
+    let mut target_urls = Vec::with_capacity(new_variants.len());
+    let mut weights = Vec::with_capacity(new_variants.len());
+
+    for variant in &new_variants {
+        if !(1..=MAX_VARIANT_WEIGHT).contains(&variant.weight) {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!("weight must be between 1 and {}", MAX_VARIANT_WEIGHT)
+            ));
+        }
+
+        target_urls.push(validate_target_url(&variant.target_url, &config)?);
+        weights.push(variant.weight);
+    }
+
+    if target_urls.iter().collect::<HashSet<_>>().len() != target_urls.len() {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "variants must have different targets"));
+    }
+
+    check_owner(&pool, &config, &link_id, &owner).await?;
+
+    let replace_variants_timeout = config.db_timeout();
+
+    timed("replace_link_variants", tokio::time::timeout(
+        replace_variants_timeout,
+        async {
+            let mut transaction = pool.begin().await?;
+
+            sqlx::query!("delete from link_variants where link_id = $1", &link_id)
+                .execute(&mut *transaction)
+                .await?;
+
+            sqlx::query!(
+                r#"
+                    insert into link_variants(link_id, target_url, weight)
+                    select $1, * from unnest($2::text[], $3::integer[])
+                "#,
+                &link_id,
+                &target_urls,
+                &weights
+            )
+            .execute(&mut *transaction)
+            .await?;
+
+            transaction.commit().await
+        }
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?;
+
+    cache.invalidate(&link_id).await;
+
+    tracing::debug!("Replaced variants of link with id {} with {} variants", link_id, target_urls.len());
+
+    let variants = target_urls
+        .into_iter()
+        .zip(weights)
+        .map(|(target_url, weight)| LinkVariant { target_url, weight })
+        .collect();
+
+    Ok(Json(variants))
+}
+
+#[utoipa::path(
+    get,
+    path = "/links/{id}/locales",
+    params(("id" = String, Path, description = "Link id")),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Locale targets of the link, empty when it has none", body = Vec<LinkLocaleTarget>),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 404, description = "Link not found", body = ErrorBody)
+    )
+)]
+pub async fn get_link_locale_targets(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Path(link_id): Path<String>
+) -> Result<Json<Vec<LinkLocaleTarget>>, ApiError> {
+    let select_timeout = config.db_timeout();
+
+    let locale_targets = timed("select_link_locale_targets", tokio::time::timeout(
+        select_timeout,
+        async {
+            let link = sqlx::query_scalar!("select id from links where id = $1", &link_id)
+                .fetch_optional(&pool)
+                .await?;
+
+            match link {
+                Some(_) => select_link_locale_targets(&pool, &link_id).await.map(Some),
+                None => Ok(None)
+            }
+        }
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?
+    .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "Not found"))?;
+
+    tracing::debug!("Locale targets of link with id {} requested", link_id);
+
+    Ok(Json(locale_targets))
+}
+
+/// Replaces all locale targets of a link. `redirect` serves the target of the
+/// visitor's most preferred language that has one and falls back to the
+/// link's variants or `targetUrl`, an empty list removes them all.
+#[utoipa::path(
+    put,
+    path = "/links/{id}/locales",
+    params(("id" = String, Path, description = "Link id")),
+    request_body = Vec<LinkLocaleTarget>,
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Locale targets stored, with lowercased languages and normalized targets", body = Vec<LinkLocaleTarget>),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 403, description = "Link belongs to another API key or target domain is not allowed", body = ErrorBody),
+        (status = 404, description = "Link not found", body = ErrorBody),
+        (status = 413, description = "Request body too large"),
+        (status = 429, description = "Rate limit exceeded", body = ErrorBody)
+    )
+)]
+pub async fn replace_link_locale_targets(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    State(cache): State<LinkCache>,
+    Extension(owner): Extension<Owner>,
+    Path(link_id): Path<String>,
+    Json(new_locale_targets): Json<Vec<LinkLocaleTarget>>
+) -> Result<Json<Vec<LinkLocaleTarget>>, ApiError> {
+    if new_locale_targets.len() > MAX_LOCALE_TARGETS {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("a link can have at most {} locale targets", MAX_LOCALE_TARGETS)
+        ));
+    }
+
+    let mut langs = Vec::with_capacity(new_locale_targets.len());
+    let mut target_urls = Vec::with_capacity(new_locale_targets.len());
+
+    for locale_target in &new_locale_targets {
+        langs.push(validate_language_tag(&locale_target.lang)?);
+        target_urls.push(validate_target_url(&locale_target.target_url, &config)?);
+    }
+
+    if langs.iter().collect::<HashSet<_>>().len() != langs.len() {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "locale targets must have different languages"));
+    }
+
+    check_owner(&pool, &config, &link_id, &owner).await?;
+
+    let replace_locale_targets_timeout = config.db_timeout();
+
+    timed("replace_link_locale_targets", tokio::time::timeout(
+        replace_locale_targets_timeout,
+        async {
+            let mut transaction = pool.begin().await?;
+
+            sqlx::query!("delete from link_locale_targets where link_id = $1", &link_id)
+                .execute(&mut *transaction)
+                .await?;
+
+            sqlx::query!(
+                r#"
+                    insert into link_locale_targets(link_id, lang, target_url)
+                    select $1, * from unnest($2::text[], $3::text[])
+                "#,
+                &link_id,
+                &langs,
+                &target_urls
+            )
+            .execute(&mut *transaction)
+            .await?;
+
+            transaction.commit().await
+        }
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?;
+
+    cache.invalidate(&link_id).await;
+
+    tracing::debug!(
+        "Replaced locale targets of link with id {} with {} locale targets",
+        link_id,
+        langs.len()
+    );
+
+    let locale_targets = langs
+        .into_iter()
+        .zip(target_urls)
+        .map(|(lang, target_url)| LinkLocaleTarget { lang, target_url })
+        .collect();
+
+    Ok(Json(locale_targets))
+}
+
+/// Lowercases a language tag after checking it is made of alphanumeric
+/// subtags of up to 8 characters separated by `-`, starting with a letter.
+fn validate_language_tag(lang: &str) -> Result<String, ApiError> {
+    let is_valid = lang.starts_with(|c: char| c.is_ascii_alphabetic())
+        && lang.split('-').all(|subtag| {
+            (1..=MAX_LANGUAGE_SUBTAG_LENGTH).contains(&subtag.len())
+                && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        });
+
+    if !is_valid {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("{} is not a valid language tag", lang)
+        ));
+    }
+
+    Ok(lang.to_ascii_lowercase())
+}
+
+/// Changes only the fields present in the body, unlike `update_link` which
+/// always replaces the target.
+#[utoipa::path(
+    patch,
+    path = "/links/{id}",
+    params(("id" = String, Path, description = "Link id")),
+    request_body = UpdateLink,
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Link updated, with its new ETag", body = Link),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 403, description = "Link belongs to another API key or target domain is not allowed", body = ErrorBody),
+        (status = 404, description = "Link not found", body = ErrorBody),
+        (status = 413, description = "Request body too large"),
+        (status = 429, description = "Rate limit exceeded", body = ErrorBody)
+    )
+)]
+pub async fn patch_link(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    State(cache): State<LinkCache>,
+    Extension(owner): Extension<Owner>,
+    Path(link_id): Path<String>,
+    Json(update_link): Json<UpdateLink>
+) -> Result<Response, ApiError> {
+    let url = update_link
+        .target_url
+        .as_deref()
+        .map(|target_url| validate_target_url(target_url, &config))
+        .transpose()?;
+    validate_link_options(&update_link.options())?;
+    validate_expires_at(update_link.expires_at.flatten(), false, &config)?;
+
+    let password_hash = match &update_link.password {
+        Some(password) => Some(password::hash(password.clone()).await?),
+        None => None
+    };
+
+    check_owner(&pool, &config, &link_id, &owner).await?;
+
+    let update_link_timeout = config.db_timeout();
+
+    let updated_link = timed("patch_link", tokio::time::timeout(
+        update_link_timeout,
+        async {
+            let mut transaction = pool.begin().await?;
+
+            let old_target = sqlx::query_scalar!(
+                "select target_url from links where id = $1 for update",
+                &link_id
+            )
+            .fetch_optional(&mut *transaction)
+            .await?;
+
+            let Some(old_target) = old_target else {
+                return Ok(None);
+            };
+
+            let updated_link = query_link!(
+                r#"
+                    with updated_link as (
+                        update links set
+                            target_url = coalesce($1, target_url),
+                            permanent = coalesce($3, permanent),
+                            expires_at = case when $4 then $5 else expires_at end,
+                            max_clicks = case when $6 then $7 else max_clicks end,
+                            forward_path = coalesce($8, forward_path),
+                            cache_control = case when $9 then $10 else cache_control end,
+                            tags = coalesce($11, tags),
+                            password_hash = coalesce($12, password_hash),
+                            referrer_policy = case when $13 then $14 else referrer_policy end,
+                            campaign_id = case when $15 then $16 else campaign_id end
+                        where id = $2
+                        returning
+                "#,
+                r#"
+                    ) select * from updated_link
+                "#,
+                url,
+                &link_id,
+                update_link.permanent,
+                // Nullable fields come with whether they were present, as
+                // `null` clears them where a missing field keeps them.
+                update_link.expires_at.is_some(),
+                update_link.expires_at.flatten(),
+                update_link.max_clicks.is_some(),
+                update_link.max_clicks.flatten(),
+                update_link.forward_path,
+                update_link.cache_control.is_some(),
+                update_link.cache_control.as_ref().and_then(Option::as_deref),
+                update_link.tags.as_deref(),
+                password_hash,
+                update_link.referrer_policy.is_some(),
+                update_link.referrer_policy.as_ref().and_then(Option::as_deref),
+                update_link.campaign_id.is_some(),
+                update_link.campaign_id.as_ref().and_then(Option::as_deref)
+            )
+            .fetch_one(&mut *transaction)
+            .await?;
+
+            record_target_change(
+                &mut transaction,
+                &config,
+                &link_id,
+                &old_target,
+                &updated_link.target_url
+            )
+            .await?;
+
+            transaction.commit().await?;
+
+            Ok(Some(updated_link))
+        }
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?
+    .ok_or_else(|| "Not found".to_string())
+    .map_err(|err| ApiError::new(StatusCode::NOT_FOUND, err))?;
+
+    cache.invalidate(&link_id).await;
+
+    tracing::debug!("Patched link with id {}", link_id);
+
+    Ok(([(ETAG, link_etag(&updated_link)?)], Json(updated_link)).into_response())
+}
+
+async fn set_link_enabled(
+    pool: &PgPool,
+    config: &Config,
+    cache: &LinkCache,
+    owner: &Owner,
+    link_id: &str,
+    enabled: bool
+) -> Result<Json<Link>, ApiError> {
+    check_owner(pool, config, link_id, owner).await?;
+
+    let update_link_timeout = config.db_timeout();
+
+    let updated_link = timed("update_link", tokio::time::timeout(
+        update_link_timeout,
+        query_link!(
+            r#"
+                with updated_link as (
+                    update links set enabled = $1
+                    where id = $2
+                    returning
+            "#,
+            r#"
+                ) select * from updated_link
+            "#,
+            enabled,
+            link_id
+        )
+        .fetch_optional(pool)
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?
+    .ok_or_else(|| "Not found".to_string())
+    .map_err(|err| ApiError::new(StatusCode::NOT_FOUND, err))?;
+
+    cache.invalidate(link_id).await;
+
+    tracing::debug!("Set enabled to {} for link with id {}", enabled, link_id);
+
+    Ok(Json(updated_link))
+}
+
+/// Stops `redirect` from resolving the link while keeping it and its statistics.
+#[utoipa::path(
+    patch,
+    path = "/links/{id}/disable",
+    params(("id" = String, Path, description = "Link id")),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Link disabled", body = Link),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 403, description = "Link belongs to another API key", body = ErrorBody),
+        (status = 404, description = "Link not found", body = ErrorBody)
+    )
+)]
+pub async fn disable_link(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    State(cache): State<LinkCache>,
+    Extension(owner): Extension<Owner>,
+    Path(link_id): Path<String>,
+) -> Result<Json<Link>, ApiError> {
+    set_link_enabled(&pool, &config, &cache, &owner, &link_id, false).await
+}
+
+#[utoipa::path(
+    patch,
+    path = "/links/{id}/enable",
+    params(("id" = String, Path, description = "Link id")),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Link enabled", body = Link),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 403, description = "Link belongs to another API key", body = ErrorBody),
+        (status = 404, description = "Link not found", body = ErrorBody)
+    )
+)]
+pub async fn enable_link(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    State(cache): State<LinkCache>,
+    Extension(owner): Extension<Owner>,
+    Path(link_id): Path<String>,
+) -> Result<Json<Link>, ApiError> {
+    set_link_enabled(&pool, &config, &cache, &owner, &link_id, true).await
+}
+
+/// Gives a link a newly generated id, after which the old one answers 404.
+/// Statistics, variants and history move along with it. Clicks of the old id
+/// still waiting in the writer's buffer are dropped.
+#[utoipa::path(
+    post,
+    path = "/links/{id}/rotate",
+    params(("id" = String, Path, description = "Link id")),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Link under its new id", body = Link),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 403, description = "Link belongs to another API key", body = ErrorBody),
+        (status = 404, description = "Link not found", body = ErrorBody)
+    )
+)]
+pub async fn rotate_link(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    State(cache): State<LinkCache>,
+    State(statistics_sink): State<Arc<dyn StatisticsSink>>,
+    Extension(owner): Extension<Owner>,
+    Path(link_id): Path<String>,
+) -> Result<Json<Link>, ApiError> {
+    check_owner(&pool, &config, &link_id, &owner).await?;
+
+    let update_link_timeout = config.db_timeout();
+
+    let mut attempts = 1;
+
+    let rotated_link = loop {
+        let new_link_id = generate_id(&config);
+
+        // One statement, the foreign keys cascade the new id to every table
+        // referencing the link.
+        let updated_link = timed("rotate_link", tokio::time::timeout(
+            update_link_timeout,
+            query_link!(
+                r#"
+                    with updated_link as (
+                        update links set id = $1
+                        where id = $2
+                        returning
+                "#,
+                r#"
+                    ) select * from updated_link
+                "#,
+                new_link_id,
+                link_id
+            )
+            .fetch_optional(&pool)
+        ))
+        .await
+        .map_err(internal_error)?;
+
+        match updated_link {
+            Ok(Some(rotated_link)) => break rotated_link,
+            Ok(None) => return Err(ApiError::new(StatusCode::NOT_FOUND, "Not found")),
+            Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
+                counter!("link_id_collisions_count").increment(1);
+
+                if attempts == MAX_ID_GENERATION_ATTEMPTS {
+                    tracing::error!(
+                        "Could not generate a unique link id after {} attempts",
+                        attempts
+                    );
+
+                    return Err(ApiError::new(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "could not generate a unique link id"
+                    ));
+                }
+
+                tracing::warn!("Generated link id {} already exists, retrying", new_link_id);
+                attempts += 1;
+            },
+            Err(err) => return Err(database_error(err))
+        }
+    };
+
+    cache.invalidate(&link_id).await;
+
+    if let Err(err) = statistics_sink.rename(&link_id, &rotated_link.id).await {
+        tracing::error!(
+            "Moving statistics of link with id {} to {} failed with the following error: {}",
+            link_id,
+            rotated_link.id,
+            err
+        );
+    }
+
+    tracing::debug!("Rotated link with id {} to {}", link_id, rotated_link.id);
+
+    Ok(Json(rotated_link))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/{id}",
+    params(("id" = String, Path, description = "Link id")),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 204, description = "Link and its statistics deleted"),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 403, description = "Link belongs to another API key", body = ErrorBody),
+        (status = 404, description = "Link not found", body = ErrorBody)
+    )
+)]
+pub async fn delete_link(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    State(cache): State<LinkCache>,
+    State(statistics_sink): State<Arc<dyn StatisticsSink>>,
+    Extension(owner): Extension<Owner>,
+    Path(link_id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    check_owner(&pool, &config, &link_id, &owner).await?;
+
+    let delete_link_timeout = config.db_timeout();
+
+    let deleted_links = timed("delete_link", tokio::time::timeout(
+        delete_link_timeout,
+        async {
+            let mut transaction = pool.begin().await?;
+
+            sqlx::query("delete from link_statistics where link_id = $1")
+                .bind(&link_id)
+                .execute(&mut *transaction)
+                .await?;
+
+            let deleted_link = sqlx::query("delete from links where id = $1")
+                .bind(&link_id)
+                .execute(&mut *transaction)
+                .await?;
+
+            transaction.commit().await?;
+
+            Ok::<u64, sqlx::Error>(deleted_link.rows_affected())
+        }
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?;
+
+    if deleted_links == 0 {
+        return Err(ApiError::new(StatusCode::NOT_FOUND, "Not found"));
+    }
+
+    cache.invalidate(&link_id).await;
+
+    if let Err(err) = statistics_sink.remove(&link_id).await {
+        tracing::error!(
+            "Removing statistics of deleted link with id {} failed with the following error: {}",
+            link_id,
+            err
+        );
+    }
+
+    tracing::debug!("Deleted link with id {} and its statistics", link_id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Deletes every link matching the filters together with its statistics, e.g.
+/// to clean up after a campaign. Links belonging to another API key are left
+/// alone.
+#[utoipa::path(
+    delete,
+    path = "/links",
+    params(LinkDeleteFilter),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Number of deleted links", body = DeletedLinks),
+        (status = 400, description = "Neither tag nor prefix given", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody)
+    )
+)]
+pub async fn delete_links(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    State(cache): State<LinkCache>,
+    State(statistics_sink): State<Arc<dyn StatisticsSink>>,
+    Extension(owner): Extension<Owner>,
+    Query(filter): Query<LinkDeleteFilter>
+) -> Result<Json<DeletedLinks>, ApiError> {
+    let tag = filter.tag.filter(|tag| !tag.is_empty());
+    let prefix = filter.prefix.filter(|prefix| !prefix.is_empty());
+
+    if tag.is_none() && prefix.is_none() {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "tag or prefix is required"));
+    }
+
+    let delete_links_timeout = config.delete_links_db_timeout();
+
+    let deleted_ids = timed("delete_links", tokio::time::timeout(
+        delete_links_timeout,
+        async {
+            let mut transaction = pool.begin().await?;
+
+            let link_ids = sqlx::query_scalar!(
+                r#"
+                    select id from links
+                    where ($1::text is null or tags @> array[$1::text])
+                        and ($2::text is null or id like $2)
+                        and (owner is null or owner = $3)
+                    for update
+                "#,
+                tag,
+                prefix.as_deref().map(like_prefix),
+                &owner.0
+            )
+            .fetch_all(&mut *transaction)
+            .await?;
+
+            sqlx::query!("delete from link_statistics where link_id = any($1)", &link_ids)
+                .execute(&mut *transaction)
+                .await?;
+
+            sqlx::query!("delete from links where id = any($1)", &link_ids)
+                .execute(&mut *transaction)
+                .await?;
+
+            transaction.commit().await?;
+
+            Ok::<Vec<String>, sqlx::Error>(link_ids)
+        }
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?;
+
+    for link_id in &deleted_ids {
+        cache.invalidate(link_id).await;
+
+        if let Err(err) = statistics_sink.remove(link_id).await {
+            tracing::error!(
+                "Removing statistics of deleted link with id {} failed with the following error: {}",
+                link_id,
+                err
+            );
+        }
+    }
+
+    tracing::debug!("Deleted {} links and their statistics", deleted_ids.len());
+
+    Ok(Json(DeletedLinks { deleted: deleted_ids.len() as u64 }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/links/{id}/qr",
+    params(("id" = String, Path, description = "Link id"), QrCodeOptions),
+    responses(
+        (status = 200, description = "QR code of the short url", content_type = "image/png", body = Vec<u8>),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 404, description = "Link not found", body = ErrorBody)
+    )
+)]
+pub async fn get_link_qr_code(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Path(link_id): Path<String>,
+    Query(options): Query<QrCodeOptions>
+) -> Result<Response, ApiError> {
+    let size = options.size.unwrap_or(DEFAULT_QR_CODE_SIZE);
+
+    if !(MIN_QR_CODE_SIZE..=MAX_QR_CODE_SIZE).contains(&size) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("size must be between {} and {}", MIN_QR_CODE_SIZE, MAX_QR_CODE_SIZE)
+        ));
+    }
+
+    let select_timeout = config.db_timeout();
+
+    timed("select_link", tokio::time::timeout(
+        select_timeout,
+        sqlx::query_scalar!("select id from links where id = $1", &link_id)
+            .fetch_optional(&pool)
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?
+    .ok_or_else(|| "Not found".to_string())
+    .map_err(|err| ApiError::new(StatusCode::NOT_FOUND, err))?;
+
+    let qr_code = QrCode::new(config.short_url(&link_id)).map_err(internal_error)?;
+
+    let image = qr_code
+        .render::<Luma<u8>>()
+        .min_dimensions(size, size)
+        .max_dimensions(size, size)
+        .build();
+
+    let mut png = Vec::new();
+
+    DynamicImage::ImageLuma8(image)
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(internal_error)?;
+
+    tracing::debug!("QR code of size {} for link with id {} requested", size, link_id);
+
+    Ok(
+        Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "image/png")
+        .header("Cache-Control", &config.cache_control_header)
+        .body(Body::from(png))
+        .expect("This response should always be constructable")
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/links/stale",
+    params(StaleLinksOptions, Pagination),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Links not accessed within the given number of days", body = Vec<Link>),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody)
+    )
+)]
+pub async fn get_stale_links(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(options): Query<StaleLinksOptions>,
+    Query(pagination): Query<Pagination>
+) -> Result<Json<Vec<Link>>, ApiError> {
+    let (limit, offset) = validate_links_pagination(&pagination)?;
+    let days = options.days.unwrap_or(DEFAULT_STALE_LINK_DAYS);
+
+    if !(1..=MAX_STALE_LINK_DAYS).contains(&days) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("days must be between 1 and {}", MAX_STALE_LINK_DAYS)
+        ));
+    }
+
+    let accessed_before = Utc::now() - chrono::Duration::days(days);
+
+    let fetch_links_timeout = config.db_timeout();
+
+    let stale_links = timed("select_stale_links", tokio::time::timeout(
+        fetch_links_timeout,
+        query_link!(
+            "select",
+            r#"
+                from links
+                where last_accessed_at is null or last_accessed_at < $1
+                order by last_accessed_at nulls first, id
+                limit $2 offset $3
+            "#,
+            accessed_before,
+            limit,
+            offset
+        )
+        .fetch_all(&pool)
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?;
+
+    tracing::debug!("{} links not accessed in {} days requested", stale_links.len(), days);
+
+    Ok(Json(stale_links))
+}
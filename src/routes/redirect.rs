@@ -0,0 +1,658 @@
+use std::net::SocketAddr;
+
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Query, RawPathParams, State};
+use axum::response::{IntoResponse, Response};
+use axum::http::header::{ACCEPT, ACCEPT_LANGUAGE, CACHE_CONTROL, VARY};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, Uri};
+use axum::Json;
+use chrono::Utc;
+use metrics::counter;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use sqlx::{PgConnection, PgPool};
+use utoipa::IntoParams;
+
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::retry::retry_reads;
+use crate::state::AppState;
+use crate::statistics::{self, LinkClick};
+use crate::utils::{
+    append_path, client_ip, database_error, internal_error, is_relative_target, merge_query, timed
+};
+
+use super::links::LinkPreview;
+use super::{
+    check_link_password, select_link_by_id, select_link_locale_targets, select_link_variants, Link,
+    LinkLocaleTarget, LinkVariant
+};
+
+const MAX_SIMILAR_IDS: i64 = 3;
+
+/// A link together with its variants and locale targets, as `redirect`
+/// resolves and caches it.
+#[derive(Clone)]
+pub struct RedirectLink {
+    pub link: Link,
+    pub variants: Vec<LinkVariant>,
+    pub locale_targets: Vec<LinkLocaleTarget>
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RedirectOptions {
+    pub notrack: Option<String>,
+    /// Password of a protected link, the `X-Link-Password` header works as well.
+    pub password: Option<String>,
+    /// Redirect with 301, 302, 307 or 308 instead of the link's status. Only
+    /// honored when `ALLOW_REDIRECT_STATUS_OVERRIDE` is on.
+    pub status: Option<String>
+}
+
+async fn select_redirect_link(
+    connection: &mut PgConnection,
+    config: &Config,
+    requested_link: &str
+) -> Result<Option<RedirectLink>, sqlx::Error> {
+    let Some(link) = select_link_by_id(&mut *connection, config, requested_link).await? else {
+        return Ok(None);
+    };
+
+    let variants = select_link_variants(&mut *connection, &link.id).await?;
+    let locale_targets = select_link_locale_targets(&mut *connection, &link.id).await?;
+
+    Ok(Some(RedirectLink { link, variants, locale_targets }))
+}
+
+/// Picks the locale target for the most preferred language of `Accept-Language`
+/// that one matches, `None` when none does. Languages are tried by quality,
+/// in the listed order for equal ones. A language matches its own tag, then
+/// a stored prefix of it (`de` for `de-at`), then a stored tag it is a prefix
+/// of (`de-at` for `de`).
+fn pick_locale_target<'a>(
+    locale_targets: &'a [LinkLocaleTarget],
+    headers: &HeaderMap
+) -> Option<&'a str> {
+    if locale_targets.is_empty() {
+        return None;
+    }
+
+    let accept_language = headers.get(ACCEPT_LANGUAGE)?.to_str().ok()?;
+
+    let mut languages: Vec<(String, f32)> = accept_language
+        .split(',')
+        .filter_map(|language_range| {
+            let mut parameters = language_range.split(';');
+            let language = parameters.next()?.trim().to_ascii_lowercase();
+            let quality = parameters
+                .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+                .find_map(|quality| quality.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            // `*` prefers no language in particular, which the link's own
+            // target already covers.
+            (!language.is_empty() && language != "*" && quality > 0.0).then_some((language, quality))
+        })
+        .collect();
+
+    // Stable, so languages of equal quality keep their order.
+    languages.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+    languages.iter().find_map(|(language, _)| {
+        let exact = locale_targets.iter().find(|locale_target| locale_target.lang == *language);
+        let broader = || {
+            locale_targets
+                .iter()
+                .find(|locale_target| is_language_prefix(&locale_target.lang, language))
+        };
+        let narrower = || {
+            locale_targets
+                .iter()
+                .find(|locale_target| is_language_prefix(language, &locale_target.lang))
+        };
+
+        exact
+            .or_else(broader)
+            .or_else(narrower)
+            .map(|locale_target| locale_target.target_url.as_str())
+    })
+}
+
+/// Whether `prefix` is `tag` cut off before one of its subtags, like `de` for `de-at`.
+fn is_language_prefix(prefix: &str, tag: &str) -> bool {
+    tag.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('-'))
+}
+
+/// Picks the target of one of `variants` by weight, `None` for links without
+/// variants.
+fn pick_variant(variants: &[LinkVariant]) -> Option<&str> {
+    let weights = WeightedIndex::new(variants.iter().map(|variant| variant.weight)).ok()?;
+
+    Some(&variants[weights.sample(&mut rand::thread_rng())].target_url)
+}
+
+/// Counts the outcome of a redirect and notes it on the request span.
+fn record_redirect_result(result: &'static str) {
+    counter!("redirects_count", "result" => result).increment(1);
+    tracing::Span::current().record("redirect_result", result);
+}
+
+/// Counts a redirect to an id that doesn't resolve and logs a sample of them,
+/// so scanners probing random ids show up without flooding the log.
+fn record_link_miss(config: &Config, requested_link: &str) {
+    record_redirect_result("miss");
+    counter!("link_not_found_count").increment(1);
+
+    if rand::thread_rng().gen_bool(config.not_found_log_sample_rate) {
+        tracing::warn!("Sampled miss for requested link with id {:?}", requested_link);
+    }
+}
+
+/// Answers a request that resolves to no link, with a redirect to
+/// `NOT_FOUND_REDIRECT` when set and a 404 otherwise.
+async fn link_not_found(
+    pool: &PgPool,
+    config: &Config,
+    requested_link: &str
+) -> Result<Response, ApiError> {
+    record_link_miss(config, requested_link);
+
+    match &config.not_found_redirect {
+        Some(not_found_redirect) => Ok(
+            Response::builder()
+            .status(StatusCode::FOUND)
+            .header("location", not_found_redirect)
+            .body(Body::empty())
+            .expect("This response should always be constructable")
+        ),
+        None if config.suggest_similar_ids => Err(
+            ApiError::new(StatusCode::NOT_FOUND, "Not found")
+                .with_suggestions(similar_link_ids(pool, config, requested_link).await)
+        ),
+        None => Err(ApiError::new(StatusCode::NOT_FOUND, "Not found"))
+    }
+}
+
+/// Up to `MAX_SIMILAR_IDS` ids of links that still redirect and whose
+/// trigram similarity to `requested_link` reaches `SIMILAR_ID_THRESHOLD`,
+/// most similar first. A failed lookup only costs the suggestions.
+async fn similar_link_ids(pool: &PgPool, config: &Config, requested_link: &str) -> Vec<String> {
+    let select_timeout = config.db_timeout();
+
+    let similar_ids = timed("select_similar_link_ids", tokio::time::timeout(
+        select_timeout,
+        async {
+            let mut transaction = pool.begin().await?;
+
+            // `%` only uses the trigram index with the session threshold, so
+            // it is set for this transaction instead of comparing `similarity`.
+            sqlx::query_scalar!(
+                "select set_config('pg_trgm.similarity_threshold', $1, true)",
+                config.similar_id_threshold.to_string()
+            )
+            .fetch_one(&mut *transaction)
+            .await?;
+
+            let similar_ids = sqlx::query_scalar!(
+                r#"
+                    select id from links
+                    where id % $1
+                        and enabled
+                        and (expires_at is null or expires_at > now())
+                        and (max_clicks is null or limited_clicks < max_clicks)
+                    order by similarity(id, $1) desc, id
+                    limit $2
+                "#,
+                requested_link,
+                MAX_SIMILAR_IDS
+            )
+            .fetch_all(&mut *transaction)
+            .await?;
+
+            transaction.commit().await?;
+
+            Ok::<Vec<String>, sqlx::Error>(similar_ids)
+        }
+    ))
+    .await;
+
+    match similar_ids {
+        Ok(Ok(similar_ids)) => similar_ids,
+        Ok(Err(err)) => {
+            tracing::error!("Looking up ids similar to {:?} failed: {}", requested_link, err);
+            Vec::new()
+        },
+        Err(_) => {
+            tracing::error!("Looking up ids similar to {:?} timed out", requested_link);
+            Vec::new()
+        }
+    }
+}
+
+/// Sets `last_accessed_at` of a followed link and bumps its `click_count`
+/// when `count_clicks` is on. Runs on every redirect, whatever the statistics
+/// setup, and the redirect is answered even when the update fails, which is
+/// only logged.
+async fn record_access(pool: &PgPool, config: &Config, link_id: &str) {
+    let recorded_access = timed("record_access", tokio::time::timeout(
+        config.db_timeout(),
+        sqlx::query!(
+            r#"
+                update links set
+                    click_count = click_count + $2,
+                    last_accessed_at = now()
+                where id = $1
+            "#,
+            link_id,
+            i64::from(config.count_clicks)
+        )
+        .execute(pool)
+    ))
+    .await;
+
+    match recorded_access {
+        Ok(Ok(_)) => {},
+        Ok(Err(err)) => tracing::error!(
+            "Recording access to link with id {} failed with the following error: {}",
+            link_id,
+            err
+        ),
+        Err(_) => tracing::error!("Recording access to link with id {} timed out", link_id)
+    }
+}
+
+/// Links with `forwardPath` also resolve `/{id}/*path`, appending the rest of
+/// the path and the query to the target.
+#[utoipa::path(
+    get,
+    path = "/{id}",
+    params(("id" = String, Path, description = "Link id"), RedirectOptions),
+    responses(
+        (status = 200, description = "Target as JSON when the Accept header prefers application/json", body = LinkPreview),
+        (status = 301, description = "Redirect to the target of a permanent link, 308 when configured"),
+        (status = 307, description = "Redirect to the target with the configured redirect status"),
+        (status = 302, description = "Unknown id, redirect to the configured not found page"),
+        (status = 400, description = "Status override is not 301, 302, 307 or 308", body = ErrorBody),
+        (status = 401, description = "Link password missing or incorrect", body = ErrorBody),
+        (status = 404, description = "Link not found", body = ErrorBody),
+        (status = 410, description = "Link expired, disabled or out of clicks", body = ErrorBody)
+    )
+)]
+pub async fn redirect(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    path_params: RawPathParams,
+    Query(options): Query<RedirectOptions>,
+    uri: Uri,
+    headers: HeaderMap
+) -> Result<Response, ApiError> {
+    let AppState { pool, config, statistics, link_cache: cache, .. } = state;
+
+    let requested_link = path_params
+        .iter()
+        .next()
+        .map(|(_, requested_link)| requested_link.to_string())
+        .unwrap_or_default();
+
+    // `/abc/` is routed here as well, ids are single segments so only one
+    // trailing slash is ever dropped.
+    let requested_link = requested_link
+        .strip_suffix('/')
+        .map(str::to_string)
+        .unwrap_or(requested_link);
+
+    tracing::Span::current().record("link_id", &requested_link);
+
+    let status_override = if config.allow_redirect_status_override {
+        options.status.as_deref().map(parse_redirect_status).transpose()?
+    } else {
+        None
+    };
+
+    // Still percent-encoded rest of the path, `foo/bar` for `/abc/foo/bar`.
+    let forwarded_path = uri
+        .path()
+        .trim_start_matches('/')
+        .split_once('/')
+        .map(|(_, forwarded_path)| forwarded_path)
+        .filter(|forwarded_path| !forwarded_path.is_empty());
+
+    let select_timeout = config.db_timeout();
+
+    // With `SYNCHRONOUS_STATISTICS` the link is read on the transaction its
+    // click is inserted on, so the cache is skipped.
+    let mut transaction = if config.synchronous_statistics {
+        Some(
+            timed("begin", tokio::time::timeout(select_timeout, pool.begin()))
+                .await
+                .map_err(internal_error)?
+                .map_err(database_error)?
+        )
+    } else {
+        None
+    };
+
+    let cached_link = match transaction {
+        Some(_) => None,
+        None => cache.get(&requested_link).await
+    };
+
+    let RedirectLink { link, variants, locale_targets } = match cached_link {
+        Some(link) => link,
+        None => {
+            let selected_link = match transaction.as_deref_mut() {
+                // A failed statement aborts the transaction, so it isn't retried.
+                Some(connection) => timed("select_link", tokio::time::timeout(
+                    select_timeout,
+                    select_redirect_link(connection, &config, &requested_link)
+                ))
+                .await,
+                None => timed("select_link", tokio::time::timeout(
+                    select_timeout,
+                    retry_reads("select_link", config.db_read_retries, || async {
+                        let mut connection = pool.acquire().await?;
+
+                        select_redirect_link(&mut connection, &config, &requested_link).await
+                    })
+                ))
+                .await
+            }
+            .map_err(internal_error)?
+            .map_err(database_error)?;
+
+            let Some(link) = selected_link else {
+                return link_not_found(&pool, &config, &requested_link).await;
+            };
+
+            cache.insert(&requested_link, link.clone()).await;
+
+            link
+        }
+    };
+
+    if forwarded_path.is_some() && !link.forward_path {
+        return link_not_found(&pool, &config, &requested_link).await;
+    }
+
+    if !link.enabled {
+        tracing::debug!("Link with id {} is disabled", requested_link);
+        record_redirect_result("disabled");
+
+        return Err(ApiError::new(StatusCode::GONE, "Link disabled"));
+    }
+
+    if link.expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+        tracing::debug!("Link with id {} has expired", requested_link);
+        record_redirect_result("expired");
+
+        return Err(ApiError::new(StatusCode::GONE, "Link expired"));
+    }
+
+    if let Err(err) = check_link_password(link.password_hash.as_deref(), &options, &headers).await {
+        tracing::debug!("Link with id {} requested without its password", requested_link);
+        record_redirect_result("unauthorized");
+
+        return Err(err);
+    }
+
+    if link.max_clicks.is_some() {
+        let claim_click_timeout = config.db_timeout();
+
+        // Counting and checking in one conditional update keeps concurrent
+        // redirects from going past the limit.
+        let claimed_click = timed("claim_click", tokio::time::timeout(
+            claim_click_timeout,
+            sqlx::query_scalar!(
+                r#"
+                    update links set
+                        limited_clicks = limited_clicks + 1,
+                        click_count = click_count + $2,
+                        last_accessed_at = now()
+                    where id = $1 and limited_clicks < max_clicks
+                    returning limited_clicks
+                "#,
+                &link.id,
+                i64::from(config.count_clicks)
+            )
+            .fetch_optional(&pool)
+        ))
+        .await
+        .map_err(internal_error)?
+        .map_err(database_error)?;
+
+        if claimed_click.is_none() {
+            tracing::debug!("Link with id {} has reached its click limit", requested_link);
+            record_redirect_result("exhausted");
+
+            return Err(ApiError::new(StatusCode::GONE, "Link click limit reached"));
+        }
+    } else {
+        record_access(&pool, &config, &link.id).await;
+    }
+
+    // A matching language takes precedence over the weighted variants.
+    let locale_target = pick_locale_target(&locale_targets, &headers);
+    let variant_url = match locale_target {
+        Some(_) => None,
+        None => pick_variant(&variants)
+    };
+    let target_url = locale_target.or(variant_url).unwrap_or(&link.target_url);
+
+    tracing::debug!(
+        "Redirecting link id {} to {}",
+        requested_link,
+        target_url
+    );
+
+    record_redirect_result("hit");
+
+    let do_not_track = config.honor_do_not_track
+        && headers.get("dnt").is_some_and(|value| value == "1");
+    let no_track_requested = matches!(options.notrack.as_deref(), Some("1" | "true"));
+
+    if do_not_track || no_track_requested {
+        tracing::debug!("Tracking skipped for click on link with id {}", requested_link);
+    } else if config.record_statistics {
+        let referer_header = headers
+            .get("referer")
+            .map(|value| value.to_str().unwrap_or_default().to_string());
+
+        let user_agent_header = headers
+            .get("user-agent")
+            .map(|value| value.to_str().unwrap_or_default().to_string());
+
+        let ip_address = client_ip(&headers, peer, config.trust_forwarded_for);
+
+        tracing::debug!(
+            "Queueing new link click for link with id {}, referer {}, user-agent {} and ip {}",
+            requested_link,
+            referer_header.as_deref().unwrap_or_default(),
+            user_agent_header.as_deref().unwrap_or_default(),
+            ip_address
+        );
+
+        let click = LinkClick {
+            link_id: link.id.clone(),
+            referer: referer_header,
+            user_agent: user_agent_header,
+            ip_address,
+            clicked_at: Utc::now(),
+            variant_url: variant_url.map(str::to_string)
+        };
+
+        match transaction {
+            Some(transaction) => {
+                statistics::save_in_transaction(transaction, click, config.db_timeout()).await
+            },
+            None => statistics.record(click)
+        }
+    }
+
+    let redirect_status = match status_override {
+        Some(status_override) => status_override,
+        None if link.permanent => config.permanent_redirect_status,
+        None => config.redirect_status
+    };
+
+    let forwarded_query = uri
+        .query()
+        .filter(|_| link.forward_path || config.preserve_query);
+
+    let relative_target = is_relative_target(target_url);
+
+    let location = if forwarded_path.is_some() || forwarded_query.is_some() || relative_target {
+        // Joining leaves absolute targets as they are.
+        let mut location = config.base_url.join(target_url).map_err(internal_error)?;
+
+        if let Some(forwarded_path) = forwarded_path {
+            append_path(&mut location, forwarded_path);
+        }
+
+        if let Some(query) = forwarded_query {
+            let skipped_params: &[&str] = if config.allow_redirect_status_override {
+                &["notrack", "password", "status"]
+            } else {
+                &["notrack", "password"]
+            };
+
+            merge_query(&mut location, query, skipped_params);
+        }
+
+        location.into()
+    } else {
+        target_url.to_string()
+    };
+
+    let cache_control = link.cache_control.as_deref().unwrap_or(&config.cache_control_header);
+    let vary = if locale_targets.is_empty() { "accept" } else { "accept, accept-language" };
+
+    // The click is recorded above either way, only the response differs.
+    if prefers_json(&headers) {
+        let mut response = Json(LinkPreview { id: link.id.clone(), target_url: location }).into_response();
+        response.headers_mut().insert(VARY, HeaderValue::from_static(vary));
+        response
+            .headers_mut()
+            .insert(CACHE_CONTROL, HeaderValue::from_str(cache_control).map_err(internal_error)?);
+
+        return Ok(response);
+    }
+
+    let location = location_header(&requested_link, &location)?;
+
+    let mut response = Response::builder()
+        .status(redirect_status)
+        .header("location", location)
+        .header("Cache-Control", cache_control)
+        .header(VARY, vary);
+
+    if let Some(referrer_policy) = link.referrer_policy.as_ref().or(config.referrer_policy.as_ref()) {
+        response = response.header("Referrer-Policy", referrer_policy);
+    }
+
+    response.body(Body::empty()).map_err(internal_error)
+}
+
+/// Stored targets passed `Url::parse`, which doesn't guarantee a legal
+/// header value, so this must not panic.
+fn location_header(link_id: &str, location: &str) -> Result<HeaderValue, ApiError> {
+    HeaderValue::from_str(location).map_err(|err| {
+        tracing::error!(
+            "Target of link with id {} is not a valid Location header: {:?}",
+            link_id,
+            location
+        );
+        internal_error(err)
+    })
+}
+
+/// Whether `Accept` ranks `application/json` at least as high as every other
+/// media range. Browsers list `text/html` first and keep being redirected,
+/// as do clients sending only `*/*`.
+fn prefers_json(headers: &HeaderMap) -> bool {
+    let Some(accept) = headers.get(ACCEPT).and_then(|value| value.to_str().ok()) else {
+        return false;
+    };
+
+    let mut json_quality: f32 = 0.0;
+    let mut other_quality: f32 = 0.0;
+
+    for media_range in accept.split(',') {
+        let mut parameters = media_range.split(';');
+        let media_type = parameters.next().unwrap_or_default().trim();
+        let quality = parameters
+            .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+            .find_map(|quality| quality.parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        if media_type.eq_ignore_ascii_case("application/json") {
+            json_quality = json_quality.max(quality);
+        } else if !media_type.is_empty() {
+            other_quality = other_quality.max(quality);
+        }
+    }
+
+    json_quality > 0.0 && json_quality >= other_quality
+}
+
+fn parse_redirect_status(status: &str) -> Result<StatusCode, ApiError> {
+    match status {
+        "301" => Ok(StatusCode::MOVED_PERMANENTLY),
+        "302" => Ok(StatusCode::FOUND),
+        "307" => Ok(StatusCode::TEMPORARY_REDIRECT),
+        "308" => Ok(StatusCode::PERMANENT_REDIRECT),
+        _ => Err(ApiError::new(StatusCode::BAD_REQUEST, "status must be one of 301, 302, 307, 308"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn location_header_rejects_line_breaks() {
+        let locations = [
+            "https://example.com/\r\nSet-Cookie: a=b",
+            "https://example.com/\n",
+            "https://example.com/\r"
+        ];
+
+        for location in locations {
+            let err = location_header("abc", location).unwrap_err();
+
+            assert_eq!(err.status, StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    #[test]
+    fn location_header_keeps_valid_targets() {
+        let location = location_header("abc", "https://example.com/a?b=c#d").unwrap();
+
+        assert_eq!(location, "https://example.com/a?b=c#d");
+    }
+
+    #[test]
+    fn prefers_json_weighs_q_values() {
+        let cases = [
+            (None, false),
+            (Some("application/json"), true),
+            (Some("*/*"), false),
+            (Some("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"), false),
+            (Some("application/json, text/html"), true),
+            (Some("text/html;q=0.5, application/json"), true),
+            (Some("application/json;q=0.5, text/html"), false),
+            (Some("application/json;q=0.8, */*;q=0.8"), true),
+            (Some("application/json;q=0"), false),
+            (Some("Application/JSON ; q=1.0"), true)
+        ];
+
+        for (accept, expected) in cases {
+            let mut headers = HeaderMap::new();
+            if let Some(accept) = accept {
+                headers.insert(ACCEPT, accept.parse().unwrap());
+            }
+
+            assert_eq!(prefers_json(&headers), expected, "{accept:?}");
+        }
+    }
+}
@@ -0,0 +1,864 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::body::{Body, Bytes};
+use axum::extract::{Extension, Path, Query, State};
+use axum::response::Response;
+use axum::http::StatusCode;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use sqlx::PgPool;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::auth::Owner;
+use crate::config::Config;
+use crate::error::ApiError;
+use crate::retry::retry_reads;
+use crate::statistics::{StatisticsError, StatisticsSink};
+use crate::utils::{csv_record, database_error, internal_error, timed};
+
+use super::{check_owner, Pagination};
+
+const DEFAULT_STATISTICS_LIMIT: i64 = 50;
+const MAX_STATISTICS_LIMIT: i64 = 500;
+
+const MAX_TIMELINE_BUCKETS: i64 = 1000;
+
+const MAX_BATCH_STATISTICS_IDS: usize = 200;
+
+const DEFAULT_TOP_LINKS_LIMIT: i64 = 10;
+const DEFAULT_TOP_REFERERS_LIMIT: i64 = 20;
+
+#[derive(serde::Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkIds {
+    pub ids: Vec<String>
+}
+
+#[derive(serde::Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CountedLinkStatistic {
+    pub amount: Option<i64>,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>
+}
+
+/// A single click as it was recorded, one line of the ndjson export.
+#[derive(serde::Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ClickRecord {
+    pub clicked_at: DateTime<Utc>,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    /// Target that was served when the link had weighted variants.
+    pub variant_url: Option<String>
+}
+
+/// Clicks grouped by the browser family and operating system parsed from the
+/// user agent.
+#[derive(serde::Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceStatistic {
+    pub browser: String,
+    pub os: String,
+    pub amount: i64
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TopLinksOptions {
+    pub limit: Option<i64>
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TopReferersOptions {
+    pub limit: Option<i64>
+}
+
+/// Restricts statistics to clicks at or after `from` and before `to`.
+#[derive(Clone, Copy, Default, serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TimeWindow {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>
+}
+
+#[derive(Clone, Copy, serde::Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TimelineBucketSize {
+    Hour,
+    Day,
+    Week
+}
+
+impl TimelineBucketSize {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TimelineBucketSize::Hour => "hour",
+            TimelineBucketSize::Day => "day",
+            TimelineBucketSize::Week => "week"
+        }
+    }
+
+    fn duration(self) -> chrono::Duration {
+        match self {
+            TimelineBucketSize::Hour => chrono::Duration::hours(1),
+            TimelineBucketSize::Day => chrono::Duration::days(1),
+            TimelineBucketSize::Week => chrono::Duration::weeks(1)
+        }
+    }
+
+    /// Window used when the client doesn't pass `from`.
+    fn default_window(self) -> chrono::Duration {
+        match self {
+            TimelineBucketSize::Hour => chrono::Duration::hours(48),
+            TimelineBucketSize::Day => chrono::Duration::days(30),
+            TimelineBucketSize::Week => chrono::Duration::weeks(12)
+        }
+    }
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TimelineOptions {
+    pub bucket: Option<TimelineBucketSize>
+}
+
+#[derive(serde::Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TimelineBucket {
+    pub bucket: DateTime<Utc>,
+    pub count: i64
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PurgeStatisticsOptions {
+    /// Only delete clicks recorded before this time.
+    pub before: Option<DateTime<Utc>>
+}
+
+#[derive(serde::Serialize, ToSchema)]
+pub struct PurgedStatistics {
+    pub deleted: u64
+}
+
+#[derive(serde::Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HeatmapOptions {
+    /// Time zone the clicks are bucketed in, e.g. `Europe/Berlin`. Defaults to UTC.
+    pub tz: Option<String>
+}
+
+/// Clicks on one day of the week, 0 being Sunday, within one hour of the day.
+#[derive(serde::Serialize, ToSchema)]
+pub struct HeatmapCell {
+    pub dow: i32,
+    pub hour: i32,
+    pub count: i64
+}
+
+/// Clicks of all links grouped under one campaign.
+#[derive(serde::Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CampaignStatistics {
+    pub campaign_id: String,
+    pub links: i64,
+    pub total_clicks: i64
+}
+
+#[derive(serde::Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TotalClicks {
+    pub link_id: String,
+    pub total_clicks: i64
+}
+
+#[derive(serde::Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TopLink {
+    pub id: String,
+    pub target_url: String,
+    pub clicks: i64
+}
+
+/// Clicks of all links coming from one referer host, `direct` for clicks
+/// without a referer.
+#[derive(serde::Serialize, ToSchema)]
+pub struct TopReferer {
+    pub referer: String,
+    pub clicks: i64
+}
+
+/// Clicks summed over every link of a campaign, an unknown campaign has no
+/// links and no clicks.
+#[utoipa::path(
+    get,
+    path = "/campaigns/{id}/statistics",
+    params(("id" = String, Path, description = "Campaign id"), TimeWindow),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Link and click count of the campaign", body = CampaignStatistics),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody)
+    )
+)]
+pub async fn get_campaign_statistics(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    State(statistics_sink): State<Arc<dyn StatisticsSink>>,
+    Path(campaign_id): Path<String>,
+    Query(window): Query<TimeWindow>
+) -> Result<Json<CampaignStatistics>, ApiError> {
+    if window.from.zip(window.to).is_some_and(|(from, to)| from > to) {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "from must not be after to"));
+    }
+
+    let fetch_statistics_timeout = config.db_timeout();
+
+    let link_ids = timed("select_campaign_links", tokio::time::timeout(
+        fetch_statistics_timeout,
+        retry_reads("select_campaign_links", config.db_read_retries, || {
+            sqlx::query_scalar!("select id from links where campaign_id = $1", &campaign_id)
+                .fetch_all(&pool)
+        })
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?;
+
+    let total_clicks = timed("count_clicks_batch", tokio::time::timeout(
+        fetch_statistics_timeout,
+        retry_reads("count_clicks_batch", config.db_read_retries, || {
+            statistics_sink.total_clicks(&link_ids, &window)
+        })
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(statistics_error)?;
+
+    tracing::debug!("Statistics of campaign {} requested", campaign_id);
+
+    Ok(Json(CampaignStatistics {
+        campaign_id,
+        links: link_ids.len() as i64,
+        total_clicks: total_clicks.values().sum()
+    }))
+}
+
+/// Deletes the recorded clicks of a link, e.g. for GDPR requests, while
+/// keeping the link. Clicks still waiting in the writer's buffer are saved
+/// afterwards.
+#[utoipa::path(
+    delete,
+    path = "/links/{id}/statistics",
+    params(("id" = String, Path, description = "Link id"), PurgeStatisticsOptions),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Number of deleted clicks", body = PurgedStatistics),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 403, description = "Link belongs to another API key", body = ErrorBody),
+        (status = 404, description = "Link not found", body = ErrorBody)
+    )
+)]
+pub async fn purge_link_statistics(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    State(statistics_sink): State<Arc<dyn StatisticsSink>>,
+    Extension(owner): Extension<Owner>,
+    Path(link_id): Path<String>,
+    Query(options): Query<PurgeStatisticsOptions>
+) -> Result<Json<PurgedStatistics>, ApiError> {
+    check_owner(&pool, &config, &link_id, &owner).await?;
+
+    let purge_statistics_timeout = config.db_timeout();
+
+    let deleted = timed("delete_statistics", tokio::time::timeout(
+        purge_statistics_timeout,
+        statistics_sink.purge(&link_id, options.before)
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(statistics_error)?;
+
+    tracing::debug!(
+        "Deleted {} clicks of link with id {} recorded before {:?}",
+        deleted,
+        link_id,
+        options.before
+    );
+
+    Ok(Json(PurgedStatistics { deleted }))
+}
+
+fn validate_statistics_query(pagination: &Pagination, window: &TimeWindow) -> Result<(i64, i64), ApiError> {
+    let limit = pagination.limit.unwrap_or(DEFAULT_STATISTICS_LIMIT);
+    let offset = pagination.offset.unwrap_or(0);
+
+    if !(1..=MAX_STATISTICS_LIMIT).contains(&limit) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("limit must be between 1 and {}", MAX_STATISTICS_LIMIT)
+        ));
+    }
+
+    if offset < 0 {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "offset must not be negative"));
+    }
+
+    if let (Some(from), Some(to)) = (window.from, window.to) {
+        if from > to {
+            return Err(ApiError::new(StatusCode::BAD_REQUEST, "from must not be after to"));
+        }
+    }
+
+    Ok((limit, offset))
+}
+
+fn statistics_error(err: StatisticsError) -> ApiError {
+    match err {
+        StatisticsError::Unsupported(message) => ApiError::new(StatusCode::BAD_REQUEST, message),
+        StatisticsError::Database(err) => database_error(err),
+        err => internal_error(err)
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/{id}/statistics",
+    params(("id" = String, Path, description = "Link id"), Pagination, TimeWindow),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Clicks grouped by referer and user agent", body = Vec<CountedLinkStatistic>),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody)
+    )
+)]
+pub async fn get_link_statistic(
+    State(statistics_sink): State<Arc<dyn StatisticsSink>>,
+    State(config): State<Arc<Config>>,
+    Path(link_id): Path<String>,
+    Query(pagination): Query<Pagination>,
+    Query(window): Query<TimeWindow>
+) -> Result<Json<Vec<CountedLinkStatistic>>, ApiError> {
+    let (limit, offset) = validate_statistics_query(&pagination, &window)?;
+
+    let fetch_statistice_timeout = config.db_timeout();
+
+    let statistics = timed("select_statistics", tokio::time::timeout(
+        fetch_statistice_timeout,
+        retry_reads("select_statistics", config.db_read_retries, || {
+            statistics_sink.counted_statistics(&link_id, limit, offset, &window)
+        })
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(statistics_error)?;
+
+    tracing::debug!("Statistics for link with id {} requested", link_id);
+
+    Ok(Json(statistics))
+}
+
+#[utoipa::path(
+    get,
+    path = "/links/{id}/statistics/devices",
+    params(("id" = String, Path, description = "Link id"), Pagination, TimeWindow),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Clicks grouped by browser and operating system", body = Vec<DeviceStatistic>),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody)
+    )
+)]
+pub async fn get_link_device_statistic(
+    State(statistics_sink): State<Arc<dyn StatisticsSink>>,
+    State(config): State<Arc<Config>>,
+    Path(link_id): Path<String>,
+    Query(pagination): Query<Pagination>,
+    Query(window): Query<TimeWindow>
+) -> Result<Json<Vec<DeviceStatistic>>, ApiError> {
+    let (limit, offset) = validate_statistics_query(&pagination, &window)?;
+
+    let fetch_statistics_timeout = config.db_timeout();
+
+    let device_counts = timed("select_statistics", tokio::time::timeout(
+        fetch_statistics_timeout,
+        retry_reads("select_statistics", config.db_read_retries, || {
+            let (statistics_sink, link_id) = (&statistics_sink, &link_id);
+
+            async move {
+                let parser = woothee::parser::Parser::new();
+                let mut device_counts: HashMap<(String, String), i64> = HashMap::new();
+
+                let mut statistics = statistics_sink
+                    .stream_counted_statistics(link_id.clone(), window)
+                    .await?;
+
+                while let Some(statistic) = statistics.try_next().await? {
+                    let device = statistic
+                        .user_agent
+                        .as_deref()
+                        .and_then(|user_agent| parser.parse(user_agent))
+                        .map(|device| (device.name.to_string(), device.os.to_string()))
+                        .unwrap_or_else(|| ("UNKNOWN".to_string(), "UNKNOWN".to_string()));
+
+                    *device_counts.entry(device).or_default() += statistic.amount.unwrap_or_default();
+                }
+
+                Ok::<_, StatisticsError>(device_counts)
+            }
+        })
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(statistics_error)?;
+
+    let mut device_statistics: Vec<DeviceStatistic> = device_counts
+        .into_iter()
+        .map(|((browser, os), amount)| DeviceStatistic { browser, os, amount })
+        .collect();
+
+    device_statistics.sort_by(|a, b| b.amount.cmp(&a.amount).then_with(|| a.browser.cmp(&b.browser)));
+
+    tracing::debug!("Device statistics for link with id {} requested", link_id);
+
+    Ok(Json(
+        device_statistics
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect()
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/links/{id}/statistics.csv",
+    params(("id" = String, Path, description = "Link id"), TimeWindow),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Grouped statistics as amount,referer,user_agent rows", content_type = "text/csv", body = String),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 404, description = "Link not found", body = ErrorBody)
+    )
+)]
+pub async fn get_link_statistic_csv(
+    State(pool): State<PgPool>,
+    State(statistics_sink): State<Arc<dyn StatisticsSink>>,
+    State(config): State<Arc<Config>>,
+    Path(link_id): Path<String>,
+    Query(window): Query<TimeWindow>
+) -> Result<Response, ApiError> {
+    if let (Some(from), Some(to)) = (window.from, window.to) {
+        if from > to {
+            return Err(ApiError::new(StatusCode::BAD_REQUEST, "from must not be after to"));
+        }
+    }
+
+    let select_timeout = config.db_timeout();
+
+    timed("select_link", tokio::time::timeout(
+        select_timeout,
+        retry_reads("select_link", config.db_read_retries, || {
+            sqlx::query_scalar!("select id from links where id = $1", &link_id)
+                .fetch_optional(&pool)
+        })
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?
+    .ok_or_else(|| "Not found".to_string())
+    .map_err(|err| ApiError::new(StatusCode::NOT_FOUND, err))?;
+
+    let statistics = timed("select_statistics", tokio::time::timeout(
+        select_timeout,
+        retry_reads("select_statistics", config.db_read_retries, || {
+            statistics_sink.stream_counted_statistics(link_id.clone(), window)
+        })
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(statistics_error)?;
+
+    let header_row = stream::once(async {
+        Ok(Bytes::from(csv_record(&["amount", "referer", "user_agent"])))
+    });
+
+    let rows = statistics.map_ok(|statistic| {
+        Bytes::from(csv_record(&[
+            &statistic.amount.unwrap_or_default().to_string(),
+            statistic.referer.as_deref().unwrap_or_default(),
+            statistic.user_agent.as_deref().unwrap_or_default()
+        ]))
+    });
+
+    tracing::debug!("Statistics export for link with id {} requested", link_id);
+
+    Ok(
+        Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "text/csv; charset=utf-8")
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}-statistics.csv\"", link_id)
+        )
+        .body(Body::from_stream(header_row.chain(rows)))
+        .expect("This response should always be constructable")
+    )
+}
+
+/// Exports every click of a link as one JSON object per line, streamed as it
+/// is read so large exports are neither buffered here nor by the client.
+#[utoipa::path(
+    get,
+    path = "/links/{id}/statistics.ndjson",
+    params(("id" = String, Path, description = "Link id"), TimeWindow),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "One click per line, oldest first", content_type = "application/x-ndjson", body = ClickRecord),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 404, description = "Link not found", body = ErrorBody)
+    )
+)]
+pub async fn get_link_clicks_ndjson(
+    State(pool): State<PgPool>,
+    State(statistics_sink): State<Arc<dyn StatisticsSink>>,
+    State(config): State<Arc<Config>>,
+    Path(link_id): Path<String>,
+    Query(window): Query<TimeWindow>
+) -> Result<Response, ApiError> {
+    if let (Some(from), Some(to)) = (window.from, window.to) {
+        if from > to {
+            return Err(ApiError::new(StatusCode::BAD_REQUEST, "from must not be after to"));
+        }
+    }
+
+    let select_timeout = config.db_timeout();
+
+    timed("select_link", tokio::time::timeout(
+        select_timeout,
+        retry_reads("select_link", config.db_read_retries, || {
+            sqlx::query_scalar!("select id from links where id = $1", &link_id)
+                .fetch_optional(&pool)
+        })
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(database_error)?
+    .ok_or_else(|| "Not found".to_string())
+    .map_err(|err| ApiError::new(StatusCode::NOT_FOUND, err))?;
+
+    let clicks = timed("select_clicks", tokio::time::timeout(
+        select_timeout,
+        retry_reads("select_clicks", config.db_read_retries, || {
+            statistics_sink.stream_clicks(link_id.clone(), window)
+        })
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(statistics_error)?;
+
+    let lines = clicks.map_ok(|click| {
+        let mut line = serde_json::to_vec(&click).expect("A click record should always serialize");
+        line.push(b'\n');
+
+        Bytes::from(line)
+    });
+
+    tracing::debug!("Click export for link with id {} requested", link_id);
+
+    Ok(
+        Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/x-ndjson")
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}-clicks.ndjson\"", link_id)
+        )
+        .body(Body::from_stream(lines))
+        .expect("This response should always be constructable")
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/links/{id}/clicks",
+    params(("id" = String, Path, description = "Link id")),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Total clicks of the link", body = TotalClicks),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody)
+    )
+)]
+pub async fn get_link_total_clicks(
+    State(statistics_sink): State<Arc<dyn StatisticsSink>>,
+    State(config): State<Arc<Config>>,
+    Path(link_id): Path<String>,
+) -> Result<Json<TotalClicks>, ApiError> {
+    let fetch_clicks_timeout = config.db_timeout();
+    let all_time = TimeWindow::default();
+
+    let counted_clicks = timed("count_clicks", tokio::time::timeout(
+        fetch_clicks_timeout,
+        retry_reads("count_clicks", config.db_read_retries, || {
+            statistics_sink.total_clicks(std::slice::from_ref(&link_id), &all_time)
+        })
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(statistics_error)?;
+
+    let total_clicks = counted_clicks.get(&link_id).copied().unwrap_or_default();
+
+    tracing::debug!("Total clicks for link with id {} requested", link_id);
+
+    Ok(Json(TotalClicks { link_id, total_clicks }))
+}
+
+/// Total clicks of several links at once, links without clicks count 0.
+#[utoipa::path(
+    post,
+    path = "/links/statistics",
+    request_body = LinkIds,
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Total clicks by link id", body = HashMap<String, i64>),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody)
+    )
+)]
+pub async fn get_links_total_clicks(
+    State(statistics_sink): State<Arc<dyn StatisticsSink>>,
+    State(config): State<Arc<Config>>,
+    Json(link_ids): Json<LinkIds>
+) -> Result<Json<HashMap<String, i64>>, ApiError> {
+    if link_ids.ids.len() > MAX_BATCH_STATISTICS_IDS {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("at most {} ids can be requested at once", MAX_BATCH_STATISTICS_IDS)
+        ));
+    }
+
+    let fetch_clicks_timeout = config.db_timeout();
+    let all_time = TimeWindow::default();
+
+    let counted_clicks = timed("count_clicks_batch", tokio::time::timeout(
+        fetch_clicks_timeout,
+        retry_reads("count_clicks_batch", config.db_read_retries, || {
+            statistics_sink.total_clicks(&link_ids.ids, &all_time)
+        })
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(statistics_error)?;
+
+    let mut total_clicks: HashMap<String, i64> = link_ids.ids
+        .iter()
+        .map(|link_id| (link_id.clone(), 0))
+        .collect();
+
+    total_clicks.extend(counted_clicks);
+
+    tracing::debug!("Total clicks for {} links requested", total_clicks.len());
+
+    Ok(Json(total_clicks))
+}
+
+#[utoipa::path(
+    get,
+    path = "/links/{id}/timeline",
+    params(("id" = String, Path, description = "Link id"), TimelineOptions, TimeWindow),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Clicks per bucket, including empty buckets", body = Vec<TimelineBucket>),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody)
+    )
+)]
+pub async fn get_link_timeline(
+    State(statistics_sink): State<Arc<dyn StatisticsSink>>,
+    State(config): State<Arc<Config>>,
+    Path(link_id): Path<String>,
+    Query(options): Query<TimelineOptions>,
+    Query(window): Query<TimeWindow>
+) -> Result<Json<Vec<TimelineBucket>>, ApiError> {
+    let bucket_size = options.bucket.unwrap_or(TimelineBucketSize::Day);
+
+    let to = window.to.unwrap_or_else(Utc::now);
+    let from = window.from.unwrap_or(to - bucket_size.default_window());
+
+    if from > to {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "from must not be after to"));
+    }
+
+    let bucket_count = (to - from).num_seconds() / bucket_size.duration().num_seconds();
+
+    if bucket_count > MAX_TIMELINE_BUCKETS {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("the requested range spans more than {} buckets", MAX_TIMELINE_BUCKETS)
+        ));
+    }
+
+    let fetch_timeline_timeout = config.db_timeout();
+
+    let timeline = timed("select_timeline", tokio::time::timeout(
+        fetch_timeline_timeout,
+        retry_reads("select_timeline", config.db_read_retries, || {
+            statistics_sink.timeline(&link_id, bucket_size, from, to)
+        })
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(statistics_error)?;
+
+    tracing::debug!("Timeline for link with id {} requested", link_id);
+
+    Ok(Json(timeline))
+}
+
+/// Clicks by day of the week and hour, all 168 cells included so the result
+/// can be drawn as a heatmap directly.
+#[utoipa::path(
+    get,
+    path = "/links/{id}/heatmap",
+    params(("id" = String, Path, description = "Link id"), HeatmapOptions, TimeWindow),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Clicks per day of the week and hour, ordered by both", body = Vec<HeatmapCell>),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody)
+    )
+)]
+pub async fn get_link_heatmap(
+    State(statistics_sink): State<Arc<dyn StatisticsSink>>,
+    State(config): State<Arc<Config>>,
+    Path(link_id): Path<String>,
+    Query(options): Query<HeatmapOptions>,
+    Query(window): Query<TimeWindow>
+) -> Result<Json<Vec<HeatmapCell>>, ApiError> {
+    if window.from.zip(window.to).is_some_and(|(from, to)| from > to) {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "from must not be after to"));
+    }
+
+    let time_zone = options.tz.as_deref().unwrap_or("UTC");
+
+    let fetch_heatmap_timeout = config.db_timeout();
+
+    let heatmap = timed("select_heatmap", tokio::time::timeout(
+        fetch_heatmap_timeout,
+        retry_reads("select_heatmap", config.db_read_retries, || {
+            statistics_sink.heatmap(&link_id, time_zone, &window)
+        })
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(|err| match err {
+        // invalid_parameter_value, raised for time zones postgres doesn't know
+        StatisticsError::Database(sqlx::Error::Database(db_err))
+            if db_err.code().as_deref() == Some("22023") => ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!("unknown time zone {}", time_zone)
+            ),
+        err => statistics_error(err)
+    })?;
+
+    tracing::debug!("Heatmap for link with id {} in time zone {} requested", link_id, time_zone);
+
+    Ok(Json(heatmap))
+}
+
+/// Links with the most recorded clicks, links never clicked are left out.
+#[utoipa::path(
+    get,
+    path = "/links/top",
+    params(TopLinksOptions),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Links ordered by clicks, most clicked first", body = Vec<TopLink>),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody)
+    )
+)]
+pub async fn get_top_links(
+    State(statistics_sink): State<Arc<dyn StatisticsSink>>,
+    State(config): State<Arc<Config>>,
+    Query(options): Query<TopLinksOptions>
+) -> Result<Json<Vec<TopLink>>, ApiError> {
+    let limit = options.limit.unwrap_or(DEFAULT_TOP_LINKS_LIMIT);
+
+    if !(1..=config.top_links_max_limit).contains(&limit) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("limit must be between 1 and {}", config.top_links_max_limit)
+        ));
+    }
+
+    let fetch_links_timeout = config.db_timeout();
+
+    let top_links = timed("select_top_links", tokio::time::timeout(
+        fetch_links_timeout,
+        retry_reads("select_top_links", config.db_read_retries, || {
+            statistics_sink.top_links(limit)
+        })
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(statistics_error)?;
+
+    tracing::debug!("Top {} links requested", limit);
+
+    Ok(Json(top_links))
+}
+
+/// Referers sending the most clicks across all links, grouped by host so
+/// different pages of the same site count together. Referers that are not
+/// urls are grouped as they were sent.
+#[utoipa::path(
+    get,
+    path = "/statistics/referers/top",
+    params(TopReferersOptions),
+    security(("api_key" = []), ("bearer" = [])),
+    responses(
+        (status = 200, description = "Referer hosts ordered by clicks, most clicks first", body = Vec<TopReferer>),
+        (status = 400, description = "Invalid input", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody)
+    )
+)]
+pub async fn get_top_referers(
+    State(statistics_sink): State<Arc<dyn StatisticsSink>>,
+    State(config): State<Arc<Config>>,
+    Query(options): Query<TopReferersOptions>
+) -> Result<Json<Vec<TopReferer>>, ApiError> {
+    let limit = options.limit.unwrap_or(DEFAULT_TOP_REFERERS_LIMIT);
+
+    if !(1..=config.top_referers_max_limit).contains(&limit) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("limit must be between 1 and {}", config.top_referers_max_limit)
+        ));
+    }
+
+    let fetch_referers_timeout = config.db_timeout();
+
+    let top_referers = timed("select_top_referers", tokio::time::timeout(
+        fetch_referers_timeout,
+        retry_reads("select_top_referers", config.db_read_retries, || {
+            statistics_sink.top_referers(limit)
+        })
+    ))
+    .await
+    .map_err(internal_error)?
+    .map_err(statistics_error)?;
+
+    tracing::debug!("Top {} referers requested", limit);
+
+    Ok(Json(top_referers))
+}
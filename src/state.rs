@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use axum::extract::FromRef;
+use tokio::time::Instant;
+use sqlx::PgPool;
+
+use crate::cache::LinkCache;
+use crate::config::Config;
+use crate::metadata::MetadataFetcher;
+use crate::rate_limit::RateLimiter;
+use crate::statistics::{StatisticsRecorder, StatisticsSink};
+use crate::target_check::TargetChecker;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: PgPool,
+    pub config: Arc<Config>,
+    pub statistics: StatisticsRecorder,
+    pub statistics_sink: Arc<dyn StatisticsSink>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub link_cache: LinkCache,
+    /// Only set when `VERIFY_TARGETS` is enabled.
+    pub target_checker: Option<TargetChecker>,
+    pub metadata_fetcher: MetadataFetcher,
+    pub started_at: Instant
+}
+
+impl FromRef<AppState> for PgPool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Config> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
+
+impl FromRef<AppState> for StatisticsRecorder {
+    fn from_ref(state: &AppState) -> Self {
+        state.statistics.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn StatisticsSink> {
+    fn from_ref(state: &AppState) -> Self {
+        state.statistics_sink.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<RateLimiter> {
+    fn from_ref(state: &AppState) -> Self {
+        state.rate_limiter.clone()
+    }
+}
+
+impl FromRef<AppState> for LinkCache {
+    fn from_ref(state: &AppState) -> Self {
+        state.link_cache.clone()
+    }
+}
+
+impl FromRef<AppState> for Option<TargetChecker> {
+    fn from_ref(state: &AppState) -> Self {
+        state.target_checker.clone()
+    }
+}
+
+impl FromRef<AppState> for MetadataFetcher {
+    fn from_ref(state: &AppState) -> Self {
+        state.metadata_fetcher.clone()
+    }
+}
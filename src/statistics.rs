@@ -0,0 +1,381 @@
+mod postgres_sink;
+mod redis_sink;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+use metrics::counter;
+use sqlx::{PgPool, Postgres, Transaction};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::oneshot;
+use tokio::task::{JoinError, JoinHandle};
+use tokio::time::{error::Elapsed, Duration};
+
+use crate::config::{Config, StatisticsSinkKind};
+use crate::routes::{
+    ClickRecord, CountedLinkStatistic, HeatmapCell, TimeWindow, TimelineBucket, TimelineBucketSize,
+    TopLink, TopReferer
+};
+use crate::utils::timed;
+
+pub use postgres_sink::PostgresSink;
+pub use redis_sink::RedisSink;
+
+pub struct LinkClick {
+    pub link_id: String,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_address: IpAddr,
+    pub clicked_at: DateTime<Utc>,
+    /// Target that was served when the link has weighted variants.
+    pub variant_url: Option<String>
+}
+
+#[derive(Debug)]
+pub enum StatisticsError {
+    Database(sqlx::Error),
+    Redis(redis::RedisError),
+    /// The active sink cannot answer the query, e.g. a time window on redis.
+    Unsupported(&'static str)
+}
+
+impl fmt::Display for StatisticsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StatisticsError::Database(err) => write!(f, "{}", err),
+            StatisticsError::Redis(err) => write!(f, "{}", err),
+            StatisticsError::Unsupported(message) => write!(f, "{}", message)
+        }
+    }
+}
+
+impl std::error::Error for StatisticsError {}
+
+impl From<sqlx::Error> for StatisticsError {
+    fn from(err: sqlx::Error) -> Self {
+        StatisticsError::Database(err)
+    }
+}
+
+impl From<redis::RedisError> for StatisticsError {
+    fn from(err: redis::RedisError) -> Self {
+        StatisticsError::Redis(err)
+    }
+}
+
+/// Storage backend for link clicks.
+///
+/// The writer task hands every batch of recorded clicks to `save`, and every
+/// statistics endpoint reads through the other methods, so a new backend only
+/// has to implement this trait and be added to `connect_sink`. Queries a
+/// backend can't answer return `StatisticsError::Unsupported`. Every sink
+/// must group clicks the same way, one entry per referer and user agent pair
+/// ordered by amount, so the HTTP response doesn't depend on the backend.
+#[async_trait]
+pub trait StatisticsSink: Send + Sync {
+    /// Persists a batch of clicks. Clicks of links that no longer exist may be dropped.
+    async fn save(&self, clicks: &[LinkClick]) -> Result<(), StatisticsError>;
+
+    /// Clicks of `link_id` grouped by referer and user agent, most frequent
+    /// first, skipping `offset` groups and returning at most `limit`.
+    async fn counted_statistics(
+        &self,
+        link_id: &str,
+        limit: i64,
+        offset: i64,
+        window: &TimeWindow
+    ) -> Result<Vec<CountedLinkStatistic>, StatisticsError>;
+
+    /// Same grouping as `counted_statistics` without pagination, yielding the
+    /// groups as they are read so exports don't have to fit in memory.
+    async fn stream_counted_statistics(
+        &self,
+        link_id: String,
+        window: TimeWindow
+    ) -> Result<BoxStream<'static, Result<CountedLinkStatistic, StatisticsError>>, StatisticsError>;
+
+    /// Every click of `link_id` within `window` without grouping, oldest
+    /// first, yielded as they are read.
+    async fn stream_clicks(
+        &self,
+        link_id: String,
+        window: TimeWindow
+    ) -> Result<BoxStream<'static, Result<ClickRecord, StatisticsError>>, StatisticsError>;
+
+    /// Called once `link_id` has been deleted, to drop whatever the sink still
+    /// stores for it.
+    async fn remove(&self, link_id: &str) -> Result<(), StatisticsError>;
+
+    /// Called once a link has been given a new id, to move whatever the sink
+    /// stores under `old_link_id` to `new_link_id`.
+    async fn rename(&self, old_link_id: &str, new_link_id: &str) -> Result<(), StatisticsError>;
+
+    /// Deletes the clicks of `link_id`, only those before `before` when given,
+    /// and returns how many were deleted. The link itself is kept.
+    async fn purge(&self, link_id: &str, before: Option<DateTime<Utc>>) -> Result<u64, StatisticsError>;
+
+    /// Clicks of each of `link_ids` within `window`, links without clicks may
+    /// be left out.
+    async fn total_clicks(
+        &self,
+        link_ids: &[String],
+        window: &TimeWindow
+    ) -> Result<HashMap<String, i64>, StatisticsError>;
+
+    /// Clicks of `link_id` per bucket from `from` up to `to`, empty buckets
+    /// included, oldest first.
+    async fn timeline(
+        &self,
+        link_id: &str,
+        bucket_size: TimelineBucketSize,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>
+    ) -> Result<Vec<TimelineBucket>, StatisticsError>;
+
+    /// Clicks of `link_id` by day of the week and hour in `time_zone`, all 168
+    /// cells included, ordered by both.
+    async fn heatmap(
+        &self,
+        link_id: &str,
+        time_zone: &str,
+        window: &TimeWindow
+    ) -> Result<Vec<HeatmapCell>, StatisticsError>;
+
+    /// The `limit` most clicked links, leaving out links never clicked.
+    async fn top_links(&self, limit: i64) -> Result<Vec<TopLink>, StatisticsError>;
+
+    /// The `limit` referer hosts sending the most clicks across all links.
+    async fn top_referers(&self, limit: i64) -> Result<Vec<TopReferer>, StatisticsError>;
+}
+
+/// Builds the sink selected by `STATISTICS_SINK`.
+pub async fn connect_sink(
+    pool: PgPool,
+    config: &Config
+) -> Result<Arc<dyn StatisticsSink>, StatisticsError> {
+    match config.statistics_sink {
+        StatisticsSinkKind::Postgres => Ok(Arc::new(PostgresSink::new(pool))),
+        StatisticsSinkKind::Redis => {
+            let redis_url = config
+                .redis_url
+                .as_deref()
+                .expect("REDIS_URL is required for the redis statistics sink");
+
+            Ok(Arc::new(RedisSink::connect(redis_url).await?))
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct StatisticsRecorder {
+    sender: mpsc::Sender<LinkClick>
+}
+
+impl StatisticsRecorder {
+    /// Queues a click for the next batched insert without waiting on the database.
+    pub fn record(&self, click: LinkClick) {
+        match self.sender.try_send(click) {
+            Ok(()) => {},
+            Err(TrySendError::Full(click)) => tracing::error!(
+                "Statistics buffer is full, dropping click for link with id {}",
+                click.link_id
+            ),
+            Err(TrySendError::Closed(click)) => tracing::error!(
+                "Statistics writer has stopped, dropping click for link with id {}",
+                click.link_id
+            )
+        }
+    }
+}
+
+/// Inserts `click` on the transaction its link was read on and commits it,
+/// bypassing the writer's buffer. Failures are logged and counted like those
+/// of a batched flush, the redirect goes ahead either way.
+pub async fn save_in_transaction(
+    mut transaction: Transaction<'static, Postgres>,
+    click: LinkClick,
+    insert_statistics_timeout: Duration
+) {
+    let saved_statistics = timed("insert_statistics", tokio::time::timeout(
+        insert_statistics_timeout,
+        async {
+            PostgresSink::insert_clicks(&mut *transaction, &[click]).await?;
+            transaction.commit().await
+        }
+    ))
+    .await;
+
+    record_saved_statistics(1, saved_statistics);
+}
+
+pub struct StatisticsWriter {
+    handle: JoinHandle<()>,
+    shutdown: oneshot::Sender<()>
+}
+
+impl StatisticsWriter {
+    /// Stops accepting clicks and waits until everything buffered has been flushed.
+    pub async fn shutdown(self) -> Result<(), JoinError> {
+        let _ = self.shutdown.send(());
+        self.handle.await
+    }
+}
+
+/// Spawns the background writer. It runs until `StatisticsWriter::shutdown` is
+/// called or every `StatisticsRecorder` has been dropped, then flushes what is left.
+pub fn spawn_writer(
+    sink: Arc<dyn StatisticsSink>,
+    config: &Config
+) -> (StatisticsRecorder, StatisticsWriter) {
+    let (sender, receiver) = mpsc::channel(config.statistics_batch_size * 10);
+    let (shutdown, shutdown_receiver) = oneshot::channel();
+
+    let handle = tokio::spawn(run_writer(
+        sink,
+        receiver,
+        shutdown_receiver,
+        config.statistics_batch_size,
+        config.statistics_flush_interval(),
+        config.db_timeout()
+    ));
+
+    (StatisticsRecorder { sender }, StatisticsWriter { handle, shutdown })
+}
+
+async fn run_writer(
+    sink: Arc<dyn StatisticsSink>,
+    mut receiver: mpsc::Receiver<LinkClick>,
+    mut shutdown: oneshot::Receiver<()>,
+    batch_size: usize,
+    flush_interval: Duration,
+    insert_statistics_timeout: Duration
+) {
+    let mut buffer = Vec::with_capacity(batch_size);
+    let mut flush_interval = tokio::time::interval(flush_interval);
+
+    loop {
+        tokio::select! {
+            click = receiver.recv() => match click {
+                Some(click) => {
+                    buffer.push(click);
+
+                    if buffer.len() >= batch_size {
+                        flush(&*sink, &mut buffer, insert_statistics_timeout).await;
+                    }
+                },
+                None => break
+            },
+            _ = flush_interval.tick() => flush(&*sink, &mut buffer, insert_statistics_timeout).await,
+            _ = &mut shutdown => break
+        }
+    }
+
+    receiver.close();
+
+    while let Some(click) = receiver.recv().await {
+        buffer.push(click);
+
+        if buffer.len() >= batch_size {
+            flush(&*sink, &mut buffer, insert_statistics_timeout).await;
+        }
+    }
+
+    flush(&*sink, &mut buffer, insert_statistics_timeout).await;
+
+    tracing::debug!("Statistics writer stopped");
+}
+
+async fn flush(
+    sink: &dyn StatisticsSink,
+    buffer: &mut Vec<LinkClick>,
+    insert_statistics_timeout: Duration
+) {
+    if buffer.is_empty() {
+        return;
+    }
+
+    let clicks = std::mem::take(buffer);
+    let amount = clicks.len();
+
+    let saved_statistics = timed("insert_statistics", tokio::time::timeout(
+        insert_statistics_timeout,
+        sink.save(&clicks)
+    ))
+    .await;
+
+    record_saved_statistics(amount, saved_statistics);
+}
+
+fn record_saved_statistics<E: fmt::Display>(amount: usize, saved_statistics: Result<Result<(), E>, Elapsed>) {
+    match saved_statistics {
+        Err(elapsed) => {
+            tracing::error!("Saving {} link clicks resulted in timeout: {}", amount, elapsed);
+            counter!("statistics_insert_failures_count").increment(amount as u64);
+        },
+        Ok(Err(err)) => {
+            tracing::error!(
+                "Saving {} link clicks failed with the following error: {}",
+                amount,
+                err
+            );
+            counter!("statistics_insert_failures_count").increment(amount as u64);
+        },
+        _ => tracing::debug!("Persisted {} link clicks", amount)
+    };
+}
+
+/// Deletes clicks older than `STATISTICS_RETENTION_DAYS` every
+/// `STATISTICS_RETENTION_INTERVAL_SECONDS`, in batches of
+/// `STATISTICS_RETENTION_BATCH_SIZE` rows so no delete holds its locks for long.
+pub fn spawn_retention(pool: PgPool, config: Arc<Config>, retention_days: u64) {
+    tokio::spawn(async move {
+        let mut retention_interval = tokio::time::interval(config.statistics_retention_interval());
+
+        loop {
+            retention_interval.tick().await;
+
+            let cutoff = Utc::now() - chrono::Duration::days(retention_days as i64);
+            let mut purged = 0;
+
+            loop {
+                let deleted_statistics = timed("delete_expired_statistics", sqlx::query!(
+                    r#"
+                        delete from link_statistics where id in (
+                            select id from link_statistics where clicked_at < $1 limit $2
+                        )
+                    "#,
+                    cutoff,
+                    config.statistics_retention_batch_size
+                )
+                .execute(&pool))
+                .await;
+
+                match deleted_statistics {
+                    Ok(deleted_statistics) => {
+                        let deleted = deleted_statistics.rows_affected();
+                        purged += deleted;
+
+                        if deleted < config.statistics_retention_batch_size as u64 {
+                            break;
+                        }
+                    },
+                    Err(err) => {
+                        tracing::error!(
+                            "Deleting link clicks before {} failed with the following error: {}",
+                            cutoff,
+                            err
+                        );
+                        break;
+                    }
+                }
+            }
+
+            tracing::info!("Purged {} link clicks older than {} days", purged, retention_days);
+        }
+    });
+}
@@ -0,0 +1,370 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, BoxStream, StreamExt};
+use sqlx::types::ipnetwork::IpNetwork;
+use sqlx::{PgExecutor, PgPool};
+use tokio::sync::mpsc;
+
+use super::{LinkClick, StatisticsError, StatisticsSink};
+use crate::routes::{
+    ClickRecord, CountedLinkStatistic, HeatmapCell, TimeWindow, TimelineBucket, TimelineBucketSize,
+    TopLink, TopReferer
+};
+
+const STREAM_BUFFER_SIZE: usize = 64;
+
+/// Stores every click as a row of `link_statistics`.
+pub struct PostgresSink {
+    pool: PgPool
+}
+
+impl PostgresSink {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// What `save` runs, on any connection so `statistics::save_in_transaction`
+    /// can pass its own.
+    pub async fn insert_clicks(
+        executor: impl PgExecutor<'_>,
+        clicks: &[LinkClick]
+    ) -> Result<(), sqlx::Error> {
+        let amount = clicks.len();
+
+        let mut link_ids = Vec::with_capacity(amount);
+        let mut referers = Vec::with_capacity(amount);
+        let mut user_agents = Vec::with_capacity(amount);
+        let mut ip_addresses = Vec::with_capacity(amount);
+        let mut clicked_ats = Vec::with_capacity(amount);
+        let mut variant_urls = Vec::with_capacity(amount);
+
+        for click in clicks {
+            link_ids.push(click.link_id.clone());
+            referers.push(click.referer.clone());
+            user_agents.push(click.user_agent.clone());
+            ip_addresses.push(IpNetwork::from(click.ip_address));
+            clicked_ats.push(click.clicked_at);
+            variant_urls.push(click.variant_url.clone());
+        }
+
+        sqlx::query(
+            r#"
+                with clicks as (
+                    select clicks.* from unnest(
+                        $1::text[], $2::text[], $3::text[], $4::inet[], $5::timestamptz[], $6::text[]
+                    ) as clicks(link_id, referer, user_agent, ip_address, clicked_at, variant_url)
+                    where exists (select 1 from links where links.id = clicks.link_id)
+                )
+                insert into link_statistics(
+                    link_id, referer, user_agent, ip_address, clicked_at, variant_url
+                )
+                select * from clicks
+            "#
+        )
+        .bind(&link_ids)
+        .bind(&referers)
+        .bind(&user_agents)
+        .bind(&ip_addresses)
+        .bind(&clicked_ats)
+        .bind(&variant_urls)
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StatisticsSink for PostgresSink {
+    async fn save(&self, clicks: &[LinkClick]) -> Result<(), StatisticsError> {
+        Self::insert_clicks(&self.pool, clicks).await?;
+
+        Ok(())
+    }
+
+    async fn counted_statistics(
+        &self,
+        link_id: &str,
+        limit: i64,
+        offset: i64,
+        window: &TimeWindow
+    ) -> Result<Vec<CountedLinkStatistic>, StatisticsError> {
+        let statistics = sqlx::query_as!(
+            CountedLinkStatistic,
+            r#"
+                select count(*) as amount, referer, user_agent from link_statistics
+                where link_id = $1
+                    and ($4::timestamptz is null or clicked_at >= $4)
+                    and ($5::timestamptz is null or clicked_at < $5)
+                group by referer, user_agent
+                order by count(*) desc limit $2 offset $3
+            "#,
+            link_id,
+            limit,
+            offset,
+            window.from,
+            window.to
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(statistics)
+    }
+
+    async fn stream_counted_statistics(
+        &self,
+        link_id: String,
+        window: TimeWindow
+    ) -> Result<BoxStream<'static, Result<CountedLinkStatistic, StatisticsError>>, StatisticsError> {
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER_SIZE);
+        let pool = self.pool.clone();
+
+        // The row stream borrows the pool, so it is drained in its own task and
+        // handed over through a channel the response body can own.
+        tokio::spawn(async move {
+            let mut statistics = sqlx::query_as!(
+                CountedLinkStatistic,
+                r#"
+                    select count(*) as amount, referer, user_agent from link_statistics
+                    where link_id = $1
+                        and ($2::timestamptz is null or clicked_at >= $2)
+                        and ($3::timestamptz is null or clicked_at < $3)
+                    group by referer, user_agent
+                    order by count(*) desc
+                "#,
+                link_id,
+                window.from,
+                window.to
+            )
+            .fetch(&pool);
+
+            while let Some(statistic) = statistics.next().await {
+                let failed = statistic.is_err();
+
+                if sender.send(statistic.map_err(StatisticsError::from)).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        Ok(
+            stream::unfold(receiver, |mut receiver| async move {
+                receiver.recv().await.map(|statistic| (statistic, receiver))
+            })
+            .boxed()
+        )
+    }
+
+    async fn stream_clicks(
+        &self,
+        link_id: String,
+        window: TimeWindow
+    ) -> Result<BoxStream<'static, Result<ClickRecord, StatisticsError>>, StatisticsError> {
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER_SIZE);
+        let pool = self.pool.clone();
+
+        tokio::spawn(async move {
+            let mut clicks = sqlx::query_as!(
+                ClickRecord,
+                r#"
+                    select clicked_at, referer, user_agent, host(ip_address) as ip_address,
+                        variant_url
+                    from link_statistics
+                    where link_id = $1
+                        and ($2::timestamptz is null or clicked_at >= $2)
+                        and ($3::timestamptz is null or clicked_at < $3)
+                    order by clicked_at, id
+                "#,
+                link_id,
+                window.from,
+                window.to
+            )
+            .fetch(&pool);
+
+            while let Some(click) = clicks.next().await {
+                let failed = click.is_err();
+
+                if sender.send(click.map_err(StatisticsError::from)).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        Ok(
+            stream::unfold(receiver, |mut receiver| async move {
+                receiver.recv().await.map(|click| (click, receiver))
+            })
+            .boxed()
+        )
+    }
+
+    async fn remove(&self, _link_id: &str) -> Result<(), StatisticsError> {
+        // `delete_link` removes the rows together with the link.
+        Ok(())
+    }
+
+    async fn rename(&self, _old_link_id: &str, _new_link_id: &str) -> Result<(), StatisticsError> {
+        // The foreign key cascades the new id to the rows.
+        Ok(())
+    }
+
+    async fn purge(&self, link_id: &str, before: Option<DateTime<Utc>>) -> Result<u64, StatisticsError> {
+        let deleted_statistics = sqlx::query!(
+            r#"
+                delete from link_statistics
+                where link_id = $1 and ($2::timestamptz is null or clicked_at < $2)
+            "#,
+            link_id,
+            before
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(deleted_statistics.rows_affected())
+    }
+
+    async fn total_clicks(
+        &self,
+        link_ids: &[String],
+        window: &TimeWindow
+    ) -> Result<HashMap<String, i64>, StatisticsError> {
+        let total_clicks = sqlx::query!(
+            r#"
+                select link_id, count(*) as "total_clicks!" from link_statistics
+                where link_id = any($1)
+                    and ($2::timestamptz is null or clicked_at >= $2)
+                    and ($3::timestamptz is null or clicked_at < $3)
+                group by link_id
+            "#,
+            link_ids,
+            window.from,
+            window.to
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(total_clicks.into_iter().map(|row| (row.link_id, row.total_clicks)).collect())
+    }
+
+    async fn timeline(
+        &self,
+        link_id: &str,
+        bucket_size: TimelineBucketSize,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>
+    ) -> Result<Vec<TimelineBucket>, StatisticsError> {
+        let timeline = sqlx::query_as!(
+            TimelineBucket,
+            r#"
+                with buckets as (
+                    select generate_series(
+                        date_trunc($2, $3::timestamptz),
+                        date_trunc($2, $4::timestamptz),
+                        ('1 ' || $2)::interval
+                    ) as bucket
+                ), clicks as (
+                    select date_trunc($2, clicked_at) as bucket, count(*) as count
+                    from link_statistics
+                    where link_id = $1 and clicked_at >= $3 and clicked_at < $4
+                    group by 1
+                )
+                select buckets.bucket as "bucket!", coalesce(clicks.count, 0) as "count!"
+                from buckets left join clicks on clicks.bucket = buckets.bucket
+                order by buckets.bucket
+            "#,
+            link_id,
+            bucket_size.as_str(),
+            from,
+            to
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(timeline)
+    }
+
+    async fn heatmap(
+        &self,
+        link_id: &str,
+        time_zone: &str,
+        window: &TimeWindow
+    ) -> Result<Vec<HeatmapCell>, StatisticsError> {
+        let heatmap = sqlx::query_as!(
+            HeatmapCell,
+            r#"
+                with cells as (
+                    select dow, hour
+                    from generate_series(0, 6) as dow cross join generate_series(0, 23) as hour
+                ), clicks as (
+                    select
+                        extract(dow from clicked_at at time zone $2)::int as dow,
+                        extract(hour from clicked_at at time zone $2)::int as hour,
+                        count(*) as count
+                    from link_statistics
+                    where link_id = $1
+                        and ($3::timestamptz is null or clicked_at >= $3)
+                        and ($4::timestamptz is null or clicked_at < $4)
+                    group by 1, 2
+                )
+                select cells.dow as "dow!", cells.hour as "hour!", coalesce(clicks.count, 0) as "count!"
+                from cells left join clicks using (dow, hour)
+                order by cells.dow, cells.hour
+            "#,
+            link_id,
+            time_zone,
+            window.from,
+            window.to
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(heatmap)
+    }
+
+    async fn top_links(&self, limit: i64) -> Result<Vec<TopLink>, StatisticsError> {
+        let top_links = sqlx::query_as!(
+            TopLink,
+            r#"
+                select links.id, links.target_url, count(*) as "clicks!" from links
+                join link_statistics on link_statistics.link_id = links.id
+                group by links.id
+                order by 3 desc, links.id
+                limit $1
+            "#,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(top_links)
+    }
+
+    async fn top_referers(&self, limit: i64) -> Result<Vec<TopReferer>, StatisticsError> {
+        let top_referers = sqlx::query_as!(
+            TopReferer,
+            r#"
+                select
+                    coalesce(
+                        lower(substring(
+                            nullif(referer, '')
+                            from '^[a-zA-Z][a-zA-Z0-9+.-]*://(?:[^@/?#]*@)?([^:/?#]+)'
+                        )),
+                        nullif(referer, ''),
+                        'direct'
+                    ) as "referer!",
+                    count(*) as "clicks!"
+                from link_statistics
+                group by 1
+                order by 2 desc, 1
+                limit $1
+            "#,
+            limit
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(top_referers)
+    }
+}
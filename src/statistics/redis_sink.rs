@@ -0,0 +1,250 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, BoxStream, StreamExt};
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use redis::AsyncCommands;
+use tokio::time::Duration;
+
+use super::{LinkClick, StatisticsError, StatisticsSink};
+use crate::routes::{
+    ClickRecord, CountedLinkStatistic, HeatmapCell, TimeWindow, TimelineBucket, TimelineBucketSize,
+    TopLink, TopReferer
+};
+
+const REDIS_CONNECTION_RETRIES: usize = 1;
+const REDIS_CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Keeps one hash per link, `link_statistics:<id>`, whose fields are the JSON
+/// encoded `[referer, user_agent]` pair and whose values are click counts.
+///
+/// Only the counts are kept, so time windows, the timeline, the heatmap and
+/// the rankings across links are unsupported while this sink is active.
+/// Which variant was served isn't kept either.
+pub struct RedisSink {
+    connection: ConnectionManager
+}
+
+impl RedisSink {
+    pub async fn connect(redis_url: &str) -> Result<Self, StatisticsError> {
+        let client = redis::Client::open(redis_url)?;
+        let connection = ConnectionManager::new_with_config(
+            client,
+            ConnectionManagerConfig::new()
+                .set_number_of_retries(REDIS_CONNECTION_RETRIES)
+                .set_connection_timeout(REDIS_CONNECTION_TIMEOUT)
+        )
+        .await?;
+
+        Ok(Self { connection })
+    }
+
+    /// Every group of `link_id`, most frequent first.
+    async fn all_counted_statistics(
+        &self,
+        link_id: &str
+    ) -> Result<Vec<CountedLinkStatistic>, StatisticsError> {
+        let counts: HashMap<String, i64> = self
+            .connection
+            .clone()
+            .hgetall(statistics_key(link_id))
+            .await?;
+
+        let mut statistics: Vec<CountedLinkStatistic> = counts
+            .into_iter()
+            .filter_map(|(field, amount)| {
+                let (referer, user_agent) = serde_json::from_str(&field).ok()?;
+
+                Some(CountedLinkStatistic {
+                    amount: Some(amount),
+                    referer,
+                    user_agent
+                })
+            })
+            .collect();
+
+        statistics.sort_by_key(|statistic| Reverse(statistic.amount));
+
+        Ok(statistics)
+    }
+}
+
+fn reject_window(window: &TimeWindow) -> Result<(), StatisticsError> {
+    if window.from.is_some() || window.to.is_some() {
+        return Err(StatisticsError::Unsupported(
+            "from and to are not supported by the redis statistics sink"
+        ));
+    }
+
+    Ok(())
+}
+
+fn statistics_key(link_id: &str) -> String {
+    format!("link_statistics:{}", link_id)
+}
+
+#[async_trait]
+impl StatisticsSink for RedisSink {
+    async fn save(&self, clicks: &[LinkClick]) -> Result<(), StatisticsError> {
+        let mut counts: HashMap<(&str, String), i64> = HashMap::new();
+
+        for click in clicks {
+            let field = serde_json::json!([click.referer, click.user_agent]).to_string();
+            *counts.entry((&click.link_id, field)).or_default() += 1;
+        }
+
+        let mut pipeline = redis::pipe();
+
+        for ((link_id, field), amount) in counts {
+            pipeline.hincr(statistics_key(link_id), field, amount).ignore();
+        }
+
+        pipeline.query_async::<()>(&mut self.connection.clone()).await?;
+
+        Ok(())
+    }
+
+    async fn counted_statistics(
+        &self,
+        link_id: &str,
+        limit: i64,
+        offset: i64,
+        window: &TimeWindow
+    ) -> Result<Vec<CountedLinkStatistic>, StatisticsError> {
+        reject_window(window)?;
+
+        Ok(
+            self.all_counted_statistics(link_id)
+                .await?
+                .into_iter()
+                .skip(offset as usize)
+                .take(limit as usize)
+                .collect()
+        )
+    }
+
+    async fn stream_counted_statistics(
+        &self,
+        link_id: String,
+        window: TimeWindow
+    ) -> Result<BoxStream<'static, Result<CountedLinkStatistic, StatisticsError>>, StatisticsError> {
+        reject_window(&window)?;
+
+        // A hash is read in one go anyway, so there is nothing to gain from
+        // streaming it.
+        let statistics = self.all_counted_statistics(&link_id).await?;
+
+        Ok(stream::iter(statistics.into_iter().map(Ok)).boxed())
+    }
+
+    async fn stream_clicks(
+        &self,
+        _link_id: String,
+        _window: TimeWindow
+    ) -> Result<BoxStream<'static, Result<ClickRecord, StatisticsError>>, StatisticsError> {
+        Err(StatisticsError::Unsupported(
+            "the redis statistics sink only keeps counts, not individual clicks"
+        ))
+    }
+
+    async fn remove(&self, link_id: &str) -> Result<(), StatisticsError> {
+        self.connection
+            .clone()
+            .del::<_, ()>(statistics_key(link_id))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn rename(&self, old_link_id: &str, new_link_id: &str) -> Result<(), StatisticsError> {
+        let mut connection = self.connection.clone();
+
+        // `RENAME` fails for a missing key, which is a link nobody clicked yet.
+        if connection.exists(statistics_key(old_link_id)).await? {
+            connection
+                .rename::<_, _, ()>(statistics_key(old_link_id), statistics_key(new_link_id))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn purge(&self, link_id: &str, before: Option<DateTime<Utc>>) -> Result<u64, StatisticsError> {
+        if before.is_some() {
+            return Err(StatisticsError::Unsupported(
+                "before is not supported by the redis statistics sink"
+            ));
+        }
+
+        let (counts,): (Vec<i64>,) = redis::pipe()
+            .atomic()
+            .hvals(statistics_key(link_id))
+            .del(statistics_key(link_id))
+            .ignore()
+            .query_async(&mut self.connection.clone())
+            .await?;
+
+        Ok(counts.into_iter().sum::<i64>() as u64)
+    }
+
+    async fn total_clicks(
+        &self,
+        link_ids: &[String],
+        window: &TimeWindow
+    ) -> Result<HashMap<String, i64>, StatisticsError> {
+        reject_window(window)?;
+
+        let mut pipeline = redis::pipe();
+
+        for link_id in link_ids {
+            pipeline.hvals(statistics_key(link_id));
+        }
+
+        let counts: Vec<Vec<i64>> = pipeline.query_async(&mut self.connection.clone()).await?;
+
+        Ok(
+            link_ids
+                .iter()
+                .cloned()
+                .zip(counts.into_iter().map(|counts| counts.into_iter().sum()))
+                .collect()
+        )
+    }
+
+    async fn timeline(
+        &self,
+        _link_id: &str,
+        _bucket_size: TimelineBucketSize,
+        _from: DateTime<Utc>,
+        _to: DateTime<Utc>
+    ) -> Result<Vec<TimelineBucket>, StatisticsError> {
+        Err(StatisticsError::Unsupported(
+            "the redis statistics sink doesn't keep when clicks happened"
+        ))
+    }
+
+    async fn heatmap(
+        &self,
+        _link_id: &str,
+        _time_zone: &str,
+        _window: &TimeWindow
+    ) -> Result<Vec<HeatmapCell>, StatisticsError> {
+        Err(StatisticsError::Unsupported(
+            "the redis statistics sink doesn't keep when clicks happened"
+        ))
+    }
+
+    async fn top_links(&self, _limit: i64) -> Result<Vec<TopLink>, StatisticsError> {
+        Err(StatisticsError::Unsupported(
+            "the redis statistics sink can't rank links"
+        ))
+    }
+
+    async fn top_referers(&self, _limit: i64) -> Result<Vec<TopReferer>, StatisticsError> {
+        Err(StatisticsError::Unsupported(
+            "the redis statistics sink can't rank referers"
+        ))
+    }
+}
@@ -0,0 +1,60 @@
+use axum::http::StatusCode;
+use reqwest::redirect::Policy;
+use reqwest::Client;
+
+use crate::config::Config;
+use crate::error::ApiError;
+
+/// Sends a `HEAD` request to the target of a new link and rejects targets
+/// that answer with an error status or can't be reached, see `VERIFY_TARGETS`.
+/// The request goes out from this service, so only enable this where it may
+/// reach whatever hosts users shorten links to.
+#[derive(Clone)]
+pub struct TargetChecker {
+    client: Client
+}
+
+impl TargetChecker {
+    pub fn new(config: &Config) -> Result<Self, reqwest::Error> {
+        let client = Client::builder()
+            .timeout(config.verify_target_timeout())
+            .redirect(Policy::limited(config.verify_target_max_redirects))
+            .build()?;
+
+        Ok(Self { client })
+    }
+
+    pub async fn check(&self, target_url: &str) -> Result<(), ApiError> {
+        let response = match self.client.head(target_url).send().await {
+            Ok(response) => response,
+            Err(err) => {
+                tracing::debug!("Target {} is unreachable: {}", target_url, err);
+
+                let reason = if err.is_redirect() {
+                    "target url redirects too often"
+                } else if err.is_timeout() {
+                    "target url did not respond in time"
+                } else {
+                    "target url is unreachable"
+                };
+
+                return Err(ApiError::new(StatusCode::BAD_REQUEST, reason));
+            }
+        };
+
+        let status = response.status();
+
+        if status.is_client_error() || status.is_server_error() {
+            tracing::debug!("Target {} answered with {}", target_url, status);
+
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!("target url answered with {}", status)
+            ));
+        }
+
+        tracing::debug!("Target {} verified with {}", target_url, status);
+
+        Ok(())
+    }
+}
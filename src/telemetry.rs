@@ -0,0 +1,185 @@
+use std::cell::Cell;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::{TraceError, TracerProvider as _};
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Config as TraceConfig, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use tower_http::trace::DefaultOnResponse;
+use tokio::time::Duration;
+use tower_http::LatencyUnit;
+use tracing::{field::Empty, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use uuid::Uuid;
+
+use crate::config::Config;
+
+const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Logs method, path, status and latency of every response, along with the
+/// link id when the handler recorded one, at `ACCESS_LOG_LEVEL`.
+pub fn access_log(config: &Config) -> DefaultOnResponse {
+    DefaultOnResponse::new()
+        .level(config.access_log_level)
+        .latency_unit(LatencyUnit::Millis)
+}
+
+/// Installs the log subscriber and, when `otlp_endpoint` is configured, a
+/// layer exporting spans to it. The returned provider has to be shut down
+/// before exiting so the last batch of spans is flushed.
+pub fn init(config: &Config) -> Result<Option<TracerProvider>, TraceError> {
+    // Shared by every layer, as a per-layer `EnvFilter` dropped the events of
+    // requests that had run a query, including their access log line.
+    let env_filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| format!("{}=debug,tower_http=debug", env!("CARGO_CRATE_NAME")).into());
+
+    let subscriber = tracing_subscriber::registry()
+        .with(env_filter)
+        .with(tracing_subscriber::fmt::layer());
+
+    let Some(otlp_endpoint) = &config.otlp_endpoint else {
+        subscriber.init();
+
+        return Ok(None);
+    };
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let tracer_provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(otlp_endpoint))
+        .with_trace_config(TraceConfig::default().with_resource(Resource::new([
+            KeyValue::new("service.name", env!("CARGO_PKG_NAME"))
+        ])))
+        .install_batch(runtime::Tokio)?;
+
+    let otel_layer = tracing_opentelemetry::layer()
+        .with_tracer(tracer_provider.tracer(env!("CARGO_PKG_NAME")));
+
+    subscriber.with(otel_layer).init();
+
+    Ok(Some(tracer_provider))
+}
+
+/// Span wrapping a whole request, continuing the trace of an incoming
+/// `traceparent` header. Handlers fill in `link_id` and `redirect_result`.
+/// The query is left out since it may carry a link password.
+pub fn request_span<B>(request: &Request<B>) -> Span {
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        path = %request.uri().path(),
+        request_id = %request
+            .headers()
+            .get(X_REQUEST_ID)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default(),
+        link_id = Empty,
+        redirect_result = Empty
+    );
+
+    let parent_context = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    span.set_parent(parent_context);
+
+    span
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+tokio::task_local! {
+    /// Time spent in `timed` queries by the request being served, `None`
+    /// until the first query finishes.
+    static DB_DURATION: Cell<Option<Duration>>;
+
+    /// Id of the request being served, set by `request_id`.
+    static REQUEST_ID: String;
+}
+
+/// Id of the request being served, `None` outside of `request_id`, e.g. in
+/// background tasks.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(String::clone).ok()
+}
+
+/// Takes the `X-Request-Id` of the client, or a new UUID when it sent none or
+/// one that is empty or longer than 128 visible characters. The id replaces
+/// the header for the layers inside, so `request_span` records it, and is
+/// echoed in the response.
+pub async fn request_id(mut req: Request<Body>, next: Next) -> Response {
+    let request_id = req
+        .headers()
+        .get(X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty() && value.len() <= MAX_REQUEST_ID_LENGTH)
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let header_value = HeaderValue::from_str(&request_id)
+        .expect("A visible ascii request id is a valid header value");
+    req.headers_mut().insert(X_REQUEST_ID, header_value.clone());
+
+    let mut response = REQUEST_ID.scope(request_id, next.run(req)).await;
+    response.headers_mut().insert(X_REQUEST_ID, header_value);
+
+    response
+}
+
+/// Adds `elapsed` to the database time of the current request. Does nothing
+/// outside of `server_timing`, e.g. in background tasks.
+pub fn record_db_duration(elapsed: Duration) {
+    let _ = DB_DURATION.try_with(|duration| {
+        duration.set(Some(duration.get().unwrap_or_default() + elapsed));
+    });
+}
+
+/// Reports the database time of a request as `Server-Timing: db;dur=<ms>`
+/// when `SERVER_TIMING` is on. Responses that ran no query get no header.
+pub async fn server_timing(
+    State(config): State<Arc<Config>>,
+    req: Request<Body>,
+    next: Next
+) -> Response {
+    if !config.server_timing {
+        return next.run(req).await;
+    }
+
+    let (mut response, db_duration) = DB_DURATION
+        .scope(Cell::new(None), async {
+            let response = next.run(req).await;
+
+            (response, DB_DURATION.with(Cell::get))
+        })
+        .await;
+
+    if let Some(db_duration) = db_duration {
+        let value = format!("db;dur={:.1}", db_duration.as_secs_f64() * 1000.0);
+        response.headers_mut().append(
+            "server-timing",
+            HeaderValue::from_str(&value).expect("A formatted duration is a valid header value")
+        );
+    }
+
+    response
+}
@@ -1,7 +1,17 @@
-use axum::http::StatusCode;
-use metrics::counter;
+use std::fmt;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
 
-pub fn internal_error<E>(err: E) -> (StatusCode, String)
+use axum::http::{HeaderMap, StatusCode};
+use metrics::{counter, histogram};
+use tokio::time::Instant;
+use tracing::{field::Empty, Instrument};
+use url::{form_urlencoded, Host, Position, Url};
+
+use crate::error::ApiError;
+use crate::telemetry;
+
+pub fn internal_error<E>(err: E) -> ApiError
 where E: std::error::Error,
 {
     tracing::error!("{}", err);
@@ -11,5 +21,433 @@ where E: std::error::Error,
     let counter = counter!("request_error", &labels);
     counter.increment(1);
 
-    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
-}
\ No newline at end of file
+    ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+}
+
+/// Like `internal_error`, but answers with a 503 when no pooled connection
+/// became available within `DB_ACQUIRE_TIMEOUT_MS`, so clients can tell an
+/// overloaded service from a broken one.
+pub fn database_error(err: sqlx::Error) -> ApiError {
+    if let sqlx::Error::PoolTimedOut = err {
+        tracing::warn!("{}", err);
+        counter!("db_pool_timeouts_count").increment(1);
+
+        return ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "database is overloaded, try again later");
+    }
+
+    internal_error(err)
+}
+
+/// Resolves the client ip, preferring the leftmost `X-Forwarded-For` entry
+/// when the deployment trusts its proxy and falling back to the socket address.
+pub fn client_ip(headers: &HeaderMap, peer: SocketAddr, trust_forwarded_for: bool) -> IpAddr {
+    if trust_forwarded_for {
+        let forwarded_ip = headers
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .and_then(|value| value.trim().parse().ok());
+
+        if let Some(ip) = forwarded_ip {
+            return ip;
+        }
+    }
+
+    peer.ip()
+}
+
+/// Records how long `future` took in the `db_query_duration_seconds` histogram,
+/// as the `duration_ms` of a `db_query` span under the current request and in
+/// its `Server-Timing` header.
+pub async fn timed<F: Future>(query: &'static str, future: F) -> F::Output {
+    let span = tracing::info_span!("db_query", query, duration_ms = Empty);
+
+    let started_at = Instant::now();
+    let output = future.instrument(span.clone()).await;
+    let elapsed = started_at.elapsed();
+
+    span.record("duration_ms", elapsed.as_secs_f64() * 1000.0);
+    histogram!("db_query_duration_seconds", "query" => query).record(elapsed.as_secs_f64());
+    telemetry::record_db_duration(elapsed);
+
+    output
+}
+
+/// Values of the `Referrer-Policy` header browsers understand.
+pub const REFERRER_POLICIES: [&str; 8] = [
+    "no-referrer",
+    "no-referrer-when-downgrade",
+    "origin",
+    "origin-when-cross-origin",
+    "same-origin",
+    "strict-origin",
+    "strict-origin-when-cross-origin",
+    "unsafe-url"
+];
+
+pub fn is_referrer_policy(policy: &str) -> bool {
+    REFERRER_POLICIES.contains(&policy)
+}
+
+#[derive(Debug)]
+pub enum UrlError {
+    Malformed,
+    UnsupportedScheme,
+    MissingHost,
+    AmbiguousPath
+}
+
+impl fmt::Display for UrlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UrlError::Malformed => write!(f, "url malformed"),
+            UrlError::UnsupportedScheme => write!(f, "only http and https URLs are allowed"),
+            UrlError::MissingHost => write!(f, "url must have a host"),
+            UrlError::AmbiguousPath => write!(f, "path-only targets must start with a single / and not contain \\")
+        }
+    }
+}
+
+impl std::error::Error for UrlError {}
+
+/// Parses a target url into the canonical form links are stored in.
+///
+/// For http(s) urls the parser already lowercases the host, drops default
+/// ports and adds the root path, so `HTTPS://Example.com:443` and
+/// `https://example.com/` both come out as `https://example.com/`. It also
+/// applies IDNA to the host, so `https://müller.de` is stored and redirected
+/// to as `https://xn--mller-kva.de/`.
+/// An empty trailing fragment is removed as well, and urls without a host,
+/// which can't be redirected to, are rejected.
+pub fn normalize_target_url(target_url: &str) -> Result<String, UrlError> {
+    let mut url = Url::parse(target_url.trim()).map_err(|_| UrlError::Malformed)?;
+
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(UrlError::UnsupportedScheme);
+    }
+
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err(UrlError::MissingHost);
+    }
+
+    if url.fragment() == Some("") {
+        url.set_fragment(None);
+    }
+
+    Ok(url.into())
+}
+
+/// Hostname in `ALLOWED_TARGET_DOMAINS` or `BLOCKED_TARGET_DOMAINS`. A
+/// leading `*.` matches every subdomain but not the domain itself, so
+/// `*.example.com` covers `a.example.com` and `a.b.example.com` only.
+#[derive(Clone)]
+pub struct DomainPattern {
+    domain: String,
+    subdomains: bool
+}
+
+impl DomainPattern {
+    /// Parses a pattern into punycode, the form hosts of normalized targets are in.
+    pub fn parse(pattern: &str) -> Result<Self, url::ParseError> {
+        let (domain, subdomains) = match pattern.strip_prefix("*.") {
+            Some(domain) => (domain, true),
+            None => (pattern, false)
+        };
+
+        Ok(Self {
+            domain: Host::parse(domain)?.to_string(),
+            subdomains
+        })
+    }
+
+    pub fn matches(&self, host: &str) -> bool {
+        if self.subdomains {
+            host.strip_suffix(self.domain.as_str())
+                .is_some_and(|prefix| prefix.len() > 1 && prefix.ends_with('.'))
+        } else {
+            host == self.domain
+        }
+    }
+}
+
+/// Whether a stored target is a path to be resolved against `BASE_URL`
+/// rather than an absolute url.
+pub fn is_relative_target(target_url: &str) -> bool {
+    target_url.starts_with('/')
+}
+
+/// Parses a path-only target like `/dashboard?tab=1` into the form it is
+/// stored in, by resolving it against `base_url` and keeping everything from
+/// the path on. `//host/path` and backslashes are rejected, as browsers would
+/// resolve them against another host.
+pub fn normalize_relative_target(target_url: &str, base_url: &Url) -> Result<String, UrlError> {
+    let target_url = target_url.trim();
+
+    if !target_url.starts_with('/') || target_url.starts_with("//") || target_url.contains('\\') {
+        return Err(UrlError::AmbiguousPath);
+    }
+
+    let mut url = base_url.join(target_url).map_err(|_| UrlError::Malformed)?;
+
+    if url.fragment() == Some("") {
+        url.set_fragment(None);
+    }
+
+    Ok(url[Position::BeforePath..].to_string())
+}
+
+/// Formats `fields` as one RFC 4180 record, quoting fields that contain a
+/// comma, a quote or a line break.
+pub fn csv_record(fields: &[&str]) -> String {
+    let mut record = fields
+        .iter()
+        .map(|field| {
+            if field.contains([',', '"', '\r', '\n']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+
+    record.push_str("\r\n");
+    record
+}
+
+/// `LIKE` pattern matching every value that starts with `prefix`, with the
+/// wildcards `%` and `_` in it escaped.
+pub fn like_prefix(prefix: &str) -> String {
+    let mut pattern = String::with_capacity(prefix.len() + 1);
+
+    for character in prefix.chars() {
+        if matches!(character, '\\' | '%' | '_') {
+            pattern.push('\\');
+        }
+        pattern.push(character);
+    }
+
+    pattern.push('%');
+    pattern
+}
+
+/// Appends the still percent-encoded `path` to the path of `url`, with exactly
+/// one slash between them.
+pub fn append_path(url: &mut Url, path: &str) {
+    let joined_path = format!(
+        "{}/{}",
+        url.path().trim_end_matches('/'),
+        path.trim_start_matches('/')
+    );
+
+    url.set_path(&joined_path);
+}
+
+/// Adds the pairs of `query` after the ones `url` already has. `skip` names
+/// parameters meant for this service rather than the target.
+pub fn merge_query(url: &mut Url, query: &str, skip: &[&str]) {
+    let pairs: Vec<_> = form_urlencoded::parse(query.as_bytes())
+        .filter(|(key, _)| !skip.contains(&key.as_ref()))
+        .collect();
+
+    if pairs.is_empty() {
+        return;
+    }
+
+    url.query_pairs_mut().extend_pairs(pairs);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_ip_trusts_forwarded_for_only_when_told() {
+        let peer = SocketAddr::from(([10, 0, 0, 1], 40000));
+        let cases: [(Option<&str>, bool, &str); 7] = [
+            (Some("203.0.113.7"), true, "203.0.113.7"),
+            (Some("203.0.113.7, 10.0.0.2"), true, "203.0.113.7"),
+            (Some(" 2001:db8::1 ,10.0.0.2"), true, "2001:db8::1"),
+            (Some("not-an-ip"), true, "10.0.0.1"),
+            (None, true, "10.0.0.1"),
+            (Some("203.0.113.7"), false, "10.0.0.1"),
+            (None, false, "10.0.0.1")
+        ];
+
+        for (forwarded_for, trust_forwarded_for, expected) in cases {
+            let mut headers = HeaderMap::new();
+            if let Some(forwarded_for) = forwarded_for {
+                headers.insert("x-forwarded-for", forwarded_for.parse().unwrap());
+            }
+
+            assert_eq!(
+                client_ip(&headers, peer, trust_forwarded_for),
+                expected.parse::<IpAddr>().unwrap(),
+                "{forwarded_for:?}, trusted: {trust_forwarded_for}"
+            );
+        }
+    }
+
+    #[test]
+    fn normalize_target_url_canonicalizes_http_urls() {
+        let cases = [
+            ("HTTPS://Example.com:443", "https://example.com/"),
+            ("http://example.com:80/a", "http://example.com/a"),
+            ("http://example.com:8080/a", "http://example.com:8080/a"),
+            ("  https://example.com/a?b=c  ", "https://example.com/a?b=c"),
+            ("https://example.com/a#", "https://example.com/a"),
+            ("https://example.com/a#top", "https://example.com/a#top")
+        ];
+
+        for (target_url, expected) in cases {
+            assert_eq!(normalize_target_url(target_url).unwrap(), expected, "{target_url}");
+        }
+    }
+
+    #[test]
+    fn normalize_target_url_rejects_other_schemes_and_garbage() {
+        for target_url in ["ftp://example.com/", "javascript:alert(1)", "mailto:a@example.com"] {
+            assert!(
+                matches!(normalize_target_url(target_url), Err(UrlError::UnsupportedScheme)),
+                "{target_url}"
+            );
+        }
+
+        for target_url in ["", "example.com", "https://exa mple.com/"] {
+            assert!(matches!(normalize_target_url(target_url), Err(UrlError::Malformed)), "{target_url}");
+        }
+    }
+
+    #[test]
+    fn csv_record_quotes_only_fields_that_need_it() {
+        let cases: [(&[&str], &str); 5] = [
+            (&["a", "b"], "a,b\r\n"),
+            (&["a,b", "c"], "\"a,b\",c\r\n"),
+            (&["say \"hi\""], "\"say \"\"hi\"\"\"\r\n"),
+            (&["line\nbreak", "cr\r"], "\"line\nbreak\",\"cr\r\"\r\n"),
+            (&["", "x"], ",x\r\n")
+        ];
+
+        for (fields, expected) in cases {
+            assert_eq!(csv_record(fields), expected, "{fields:?}");
+        }
+    }
+
+    #[test]
+    fn append_path_joins_with_a_single_slash() {
+        let cases = [
+            ("https://example.com/docs", "guide/intro", "https://example.com/docs/guide/intro"),
+            ("https://example.com/docs/", "/guide", "https://example.com/docs/guide"),
+            ("https://example.com/", "a%20b", "https://example.com/a%20b"),
+            ("https://example.com/docs?v=1", "guide", "https://example.com/docs/guide?v=1")
+        ];
+
+        for (target_url, path, expected) in cases {
+            let mut url = Url::parse(target_url).unwrap();
+            append_path(&mut url, path);
+
+            assert_eq!(url.as_str(), expected, "{target_url} + {path}");
+        }
+    }
+
+    #[test]
+    fn like_prefix_escapes_wildcards() {
+        let cases = [
+            ("camp", "camp%"),
+            ("50%_off", "50\\%\\_off%"),
+            ("back\\slash", "back\\\\slash%"),
+            ("", "%")
+        ];
+
+        for (prefix, expected) in cases {
+            assert_eq!(like_prefix(prefix), expected, "{prefix}");
+        }
+    }
+
+    #[test]
+    fn merge_query_appends_pairs_except_skipped_ones() {
+        let skip = ["notrack", "password"];
+        let cases = [
+            ("https://example.com/", "a=1", "https://example.com/?a=1"),
+            ("https://example.com/?a=1", "b=2&c=3", "https://example.com/?a=1&b=2&c=3"),
+            ("https://example.com/?a=1", "notrack=1&password=secret", "https://example.com/?a=1"),
+            ("https://example.com/", "notrack&b=x%20y", "https://example.com/?b=x+y"),
+            ("https://example.com/", "", "https://example.com/")
+        ];
+
+        for (target_url, query, expected) in cases {
+            let mut url = Url::parse(target_url).unwrap();
+            merge_query(&mut url, query, &skip);
+
+            assert_eq!(url.as_str(), expected, "{target_url} + {query}");
+        }
+    }
+
+    #[test]
+    fn normalize_target_url_rejects_urls_without_a_host() {
+        // The parser already refuses most of these, `MissingHost` is the backstop.
+        for target_url in ["https://", "https:", "https:?query", "https://#top", "http://:80/", "https://@/"] {
+            assert!(normalize_target_url(target_url).is_err(), "{target_url}");
+        }
+    }
+
+    #[test]
+    fn normalize_target_url_stores_idn_hosts_as_punycode() {
+        let cases = [
+            ("https://müller.de", "https://xn--mller-kva.de/"),
+            ("https://MÜLLER.de/straße", "https://xn--mller-kva.de/stra%C3%9Fe"),
+            ("https://xn--mller-kva.de/", "https://xn--mller-kva.de/"),
+            ("http://例え.jp/", "http://xn--r8jz45g.jp/")
+        ];
+
+        for (target_url, expected) in cases {
+            assert_eq!(normalize_target_url(target_url).unwrap(), expected, "{target_url}");
+        }
+    }
+
+    #[test]
+    fn normalize_relative_target_keeps_the_path_on() {
+        let base_url = Url::parse("https://sho.rt/app/").unwrap();
+        let cases = [
+            ("/dashboard", "/dashboard"),
+            ("/dashboard?tab=1#", "/dashboard?tab=1"),
+            ("/a/../b c", "/b%20c"),
+            (" /docs#intro ", "/docs#intro")
+        ];
+
+        for (target_url, expected) in cases {
+            assert_eq!(normalize_relative_target(target_url, &base_url).unwrap(), expected, "{target_url}");
+        }
+    }
+
+    #[test]
+    fn normalize_relative_target_rejects_paths_browsers_resolve_elsewhere() {
+        let base_url = Url::parse("https://sho.rt/").unwrap();
+
+        for target_url in ["//evil.example/path", "/\\evil.example", "/a\\b", "dashboard", ""] {
+            assert!(
+                matches!(normalize_relative_target(target_url, &base_url), Err(UrlError::AmbiguousPath)),
+                "{target_url}"
+            );
+        }
+    }
+
+    #[test]
+    fn domain_pattern_matches_exact_domains_and_wildcard_subdomains() {
+        let cases = [
+            ("example.com", "example.com", true),
+            ("example.com", "a.example.com", false),
+            ("*.example.com", "a.example.com", true),
+            ("*.example.com", "a.b.example.com", true),
+            ("*.example.com", "example.com", false),
+            ("*.example.com", "badexample.com", false),
+            ("*.example.com", ".example.com", false),
+            ("Example.COM", "example.com", true),
+            ("*.müller.de", "shop.xn--mller-kva.de", true)
+        ];
+
+        for (pattern, host, expected) in cases {
+            assert_eq!(DomainPattern::parse(pattern).unwrap().matches(host), expected, "{pattern} ~ {host}");
+        }
+    }
+}
@@ -0,0 +1,634 @@
+mod common;
+
+use axum::http::{header, Method, StatusCode};
+use axum_test::{TestResponse, TestServer};
+use common::{app, database, API_KEY};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+
+async fn create_link(server: &TestServer, target_url: &str) -> String {
+    let response = server
+        .post("/create")
+        .add_header("x-api-key", API_KEY)
+        .json(&json!({ "targetUrl": target_url }))
+        .await;
+    response.assert_status(StatusCode::CREATED);
+
+    response.json::<Value>()["id"].as_str().expect("Link has an id").to_owned()
+}
+
+async fn follow(server: &TestServer, link_id: &str, referer: &str) -> TestResponse {
+    server
+        .get(&format!("/{link_id}"))
+        .add_header(header::REFERER, referer)
+        .await
+}
+
+async fn count_clicks(pool: &PgPool, link_id: &str) -> i64 {
+    sqlx::query_scalar("select count(*) from link_statistics where link_id = $1")
+        .bind(link_id)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn redirect_goes_to_the_target_and_records_one_click_each() {
+    let database = database().await;
+    let (server, statistics_writer) = app(&database).await;
+    let link_id = create_link(&server, "https://example.com/landing").await;
+
+    for _ in 0..3 {
+        let response = follow(&server, &link_id, "https://referer.example/").await;
+
+        response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+        response.assert_header(header::LOCATION, "https://example.com/landing");
+    }
+
+    statistics_writer.shutdown().await.unwrap();
+
+    assert_eq!(count_clicks(&database.pool, &link_id).await, 3);
+}
+
+#[tokio::test]
+async fn redirect_to_unknown_link_is_not_found() {
+    let database = database().await;
+    let (server, statistics_writer) = app(&database).await;
+
+    follow(&server, "missing", "https://referer.example/")
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    statistics_writer.shutdown().await.unwrap();
+
+    assert_eq!(count_clicks(&database.pool, "missing").await, 0);
+}
+
+#[tokio::test]
+async fn update_link_changes_where_it_redirects() {
+    let database = database().await;
+    let (server, _statistics_writer) = app(&database).await;
+    let link_id = create_link(&server, "https://example.com/old").await;
+
+    let response = server
+        .patch(&format!("/{link_id}"))
+        .add_header("x-api-key", API_KEY)
+        .json(&json!({ "targetUrl": "https://example.com/new" }))
+        .await;
+    response.assert_status(StatusCode::OK);
+    assert_eq!(response.json::<Value>()["targetUrl"], "https://example.com/new");
+
+    let response = follow(&server, &link_id, "https://referer.example/").await;
+    response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+    response.assert_header(header::LOCATION, "https://example.com/new");
+}
+
+#[tokio::test]
+async fn update_link_of_unknown_id_is_not_found() {
+    let database = database().await;
+    let (server, _statistics_writer) = app(&database).await;
+
+    server
+        .patch("/missing")
+        .add_header("x-api-key", API_KEY)
+        .json(&json!({ "targetUrl": "https://example.com/new" }))
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn statistics_count_clicks_per_referer() {
+    let database = database().await;
+    let (server, statistics_writer) = app(&database).await;
+    let link_id = create_link(&server, "https://example.com/landing").await;
+
+    for referer in ["https://a.example/", "https://a.example/", "https://b.example/"] {
+        follow(&server, &link_id, referer).await;
+    }
+
+    statistics_writer.shutdown().await.unwrap();
+
+    let response = server
+        .get(&format!("/{link_id}/statistics"))
+        .add_header("x-api-key", API_KEY)
+        .await;
+    response.assert_status(StatusCode::OK);
+
+    let mut amounts: Vec<_> = response
+        .json::<Value>()
+        .as_array()
+        .expect("Statistics are a list")
+        .iter()
+        .map(|row| (row["referer"].as_str().unwrap().to_owned(), row["amount"].as_i64().unwrap()))
+        .collect();
+    amounts.sort();
+
+    assert_eq!(
+        amounts,
+        [("https://a.example/".to_owned(), 2), ("https://b.example/".to_owned(), 1)]
+    );
+}
+
+#[tokio::test]
+async fn statistics_need_an_api_key() {
+    let database = database().await;
+    let (server, _statistics_writer) = app(&database).await;
+    let link_id = create_link(&server, "https://example.com/landing").await;
+
+    server
+        .get(&format!("/{link_id}/statistics"))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn head_health_answers_without_a_body() {
+    let database = database().await;
+    let (server, _statistics_writer) = app(&database).await;
+
+    let response = server.method(Method::HEAD, "/health").await;
+
+    response.assert_status(StatusCode::OK);
+    assert!(response.as_bytes().is_empty());
+}
+
+#[tokio::test]
+async fn metadata_needs_an_api_key() {
+    let database = database().await;
+    let (server, _statistics_writer) = app(&database).await;
+    let link_id = create_link(&server, "https://example.com/landing").await;
+
+    server
+        .get(&format!("/links/{link_id}/metadata"))
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn total_clicks_count_every_redirect() {
+    let database = database().await;
+    let (server, statistics_writer) = app(&database).await;
+    let link_id = create_link(&server, "https://example.com/landing").await;
+
+    for _ in 0..2 {
+        follow(&server, &link_id, "https://referer.example/").await;
+    }
+
+    statistics_writer.shutdown().await.unwrap();
+
+    let response = server
+        .get(&format!("/links/{link_id}/clicks"))
+        .add_header("x-api-key", API_KEY)
+        .await;
+    response.assert_status(StatusCode::OK);
+
+    assert_eq!(response.json::<Value>()["totalClicks"], 2);
+}
+
+#[tokio::test]
+async fn synchronous_statistics_save_the_click_before_redirecting() {
+    let mut config = common::config();
+    config.synchronous_statistics = true;
+
+    let database = database().await;
+    let (server, _statistics_writer) = common::app_with_config(&database, config).await;
+    let link_id = create_link(&server, "https://example.com/landing").await;
+
+    let response = follow(&server, &link_id, "https://referer.example/").await;
+    response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+    response.assert_header(header::LOCATION, "https://example.com/landing");
+
+    // Counted without shutting the writer down, the click never went through it.
+    assert_eq!(count_clicks(&database.pool, &link_id).await, 1);
+}
+
+#[tokio::test]
+async fn synchronous_statistics_still_redirect_when_the_insert_fails() {
+    let mut config = common::config();
+    config.synchronous_statistics = true;
+
+    let database = database().await;
+    let (server, _statistics_writer) = common::app_with_config(&database, config).await;
+    let link_id = create_link(&server, "https://example.com/landing").await;
+
+    sqlx::query("drop table link_statistics").execute(&database.pool).await.unwrap();
+
+    let response = follow(&server, &link_id, "https://referer.example/").await;
+    response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+    response.assert_header(header::LOCATION, "https://example.com/landing");
+}
+
+#[tokio::test]
+async fn bulk_create_returns_the_links_in_request_order() {
+    let database = database().await;
+    let (server, _statistics_writer) = app(&database).await;
+
+    let response = server
+        .post("/links/bulk")
+        .add_header("x-api-key", API_KEY)
+        .json(&json!([
+            { "targetUrl": "https://example.com/first", "customId": "bulk-first", "tags": ["a", "b"] },
+            { "targetUrl": "https://example.com/second", "customId": "bulk-second", "password": "secret" },
+            { "targetUrl": "https://example.com/third" }
+        ]))
+        .await;
+    response.assert_status(StatusCode::OK);
+
+    let created_links = response.json::<Value>();
+    assert_eq!(created_links[0]["id"], "bulk-first");
+    assert_eq!(created_links[0]["tags"], json!(["a", "b"]));
+    assert_eq!(created_links[1]["id"], "bulk-second");
+    assert_eq!(created_links[2]["targetUrl"], "https://example.com/third");
+
+    follow(&server, "bulk-second", "https://referer.example/")
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn bulk_create_with_a_taken_id_creates_nothing() {
+    let database = database().await;
+    let (server, _statistics_writer) = app(&database).await;
+
+    server
+        .post("/links/bulk")
+        .add_header("x-api-key", API_KEY)
+        .json(&json!([
+            { "targetUrl": "https://example.com/first", "customId": "bulk-taken" },
+            { "targetUrl": "https://example.com/second", "customId": "bulk-taken" }
+        ]))
+        .await
+        .assert_status(StatusCode::CONFLICT);
+
+    let links: i64 = sqlx::query_scalar("select count(*) from links")
+        .fetch_one(&database.pool)
+        .await
+        .unwrap();
+    assert_eq!(links, 0);
+}
+
+#[tokio::test]
+async fn ids_only_differing_in_case_are_taken_with_case_insensitive_ids() {
+    let mut config = common::config();
+    config.case_insensitive_ids = true;
+
+    let database = database().await;
+    let (server, _statistics_writer) = common::app_with_config(&database, config).await;
+
+    for (custom_id, status) in [("case", StatusCode::CREATED), ("CASE", StatusCode::CONFLICT)] {
+        server
+            .post("/create")
+            .add_header("x-api-key", API_KEY)
+            .json(&json!({ "targetUrl": "https://example.com/landing", "customId": custom_id }))
+            .await
+            .assert_status(status);
+    }
+
+    server
+        .post("/links/bulk")
+        .add_header("x-api-key", API_KEY)
+        .json(&json!([
+            { "targetUrl": "https://example.com/first", "customId": "bulk-taken" },
+            { "targetUrl": "https://example.com/second", "customId": "BULK-TAKEN" }
+        ]))
+        .await
+        .assert_status(StatusCode::CONFLICT);
+
+    let links: i64 = sqlx::query_scalar("select count(*) from links")
+        .fetch_one(&database.pool)
+        .await
+        .unwrap();
+    assert_eq!(links, 1);
+}
+
+#[tokio::test]
+async fn ids_only_differing_in_case_are_distinct_links_by_default() {
+    let database = database().await;
+    let (server, _statistics_writer) = app(&database).await;
+
+    for (custom_id, target_url) in [("case", "https://example.com/lower"), ("CASE", "https://example.com/upper")] {
+        server
+            .post("/create")
+            .add_header("x-api-key", API_KEY)
+            .json(&json!({ "targetUrl": target_url, "customId": custom_id }))
+            .await
+            .assert_status(StatusCode::CREATED);
+    }
+
+    server.get("/CASE").await.assert_header(header::LOCATION, "https://example.com/upper");
+}
+
+#[tokio::test]
+async fn update_link_with_a_custom_id_is_rejected() {
+    let database = database().await;
+    let (server, _statistics_writer) = app(&database).await;
+    let link_id = create_link(&server, "https://example.com/old").await;
+
+    server
+        .patch(&format!("/{link_id}"))
+        .add_header("x-api-key", API_KEY)
+        .json(&json!({ "targetUrl": "https://example.com/new", "customId": "renamed" }))
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+
+    let response = follow(&server, &link_id, "https://referer.example/").await;
+    response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+    response.assert_header(header::LOCATION, "https://example.com/old");
+}
+
+#[tokio::test]
+async fn stale_links_are_paginated() {
+    let database = database().await;
+    let (server, _statistics_writer) = app(&database).await;
+    let mut link_ids = [
+        create_link(&server, "https://example.com/first").await,
+        create_link(&server, "https://example.com/second").await,
+        create_link(&server, "https://example.com/third").await
+    ];
+    link_ids.sort();
+
+    let response = server
+        .get("/links/stale?limit=2&offset=1")
+        .add_header("x-api-key", API_KEY)
+        .await;
+    response.assert_status(StatusCode::OK);
+
+    let stale_links = response.json::<Value>();
+    let stale_ids: Vec<&str> = stale_links
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|link| link["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(stale_ids, link_ids[1..]);
+
+    server
+        .get("/links/stale?limit=0")
+        .add_header("x-api-key", API_KEY)
+        .await
+        .assert_status(StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn followed_links_are_not_stale_without_statistics() {
+    let mut config = common::config();
+    config.record_statistics = false;
+
+    let database = database().await;
+    let (server, statistics_writer) = common::app_with_config(&database, config).await;
+    let followed_id = create_link(&server, "https://example.com/followed").await;
+    let unused_id = create_link(&server, "https://example.com/unused").await;
+
+    follow(&server, &followed_id, "https://referer.example/")
+        .await
+        .assert_status(StatusCode::TEMPORARY_REDIRECT);
+
+    statistics_writer.shutdown().await.unwrap();
+    assert_eq!(count_clicks(&database.pool, &followed_id).await, 0);
+
+    let response = server
+        .get("/links/stale?days=1")
+        .add_header("x-api-key", API_KEY)
+        .await;
+    response.assert_status(StatusCode::OK);
+
+    let stale_links = response.json::<Value>();
+    let stale_ids: Vec<&str> = stale_links
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|link| link["id"].as_str().unwrap())
+        .collect();
+    assert_eq!(stale_ids, [unused_id.as_str()]);
+}
+
+#[tokio::test]
+async fn create_rejects_a_body_over_the_limit() {
+    let database = database().await;
+    let (server, _statistics_writer) = app(&database).await;
+
+    server
+        .post("/create")
+        .add_header("x-api-key", API_KEY)
+        .json(&json!({ "targetUrl": format!("https://example.com/{}", "a".repeat(1024 * 1024)) }))
+        .await
+        .assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+
+    server
+        .post("/create")
+        .add_header("x-api-key", API_KEY)
+        .json(&json!({ "targetUrl": "https://example.com/landing", "tags": ["a", "b"] }))
+        .await
+        .assert_status(StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn trailing_slash_resolves_the_same_link() {
+    let database = database().await;
+    let (server, statistics_writer) = app(&database).await;
+
+    server
+        .post("/create")
+        .add_header("x-api-key", API_KEY)
+        .json(&json!({ "targetUrl": "https://example.com/landing", "customId": "abc" }))
+        .await
+        .assert_status(StatusCode::CREATED);
+
+    for path in ["/abc", "/abc/"] {
+        let response = server.get(path).await;
+        response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+        response.assert_header(header::LOCATION, "https://example.com/landing");
+    }
+
+    statistics_writer.shutdown().await.unwrap();
+
+    assert_eq!(count_clicks(&database.pool, "abc").await, 2);
+}
+
+#[tokio::test]
+async fn unsupported_methods_are_not_allowed_with_an_allow_header() {
+    let database = database().await;
+    let (server, _statistics_writer) = app(&database).await;
+
+    // Without an API key, the 405 comes before authentication.
+    for (method, path, allowed) in [
+        (Method::POST, "/abc", ["DELETE", "GET", "HEAD", "PATCH"].as_slice()),
+        (Method::PUT, "/links", ["DELETE", "GET", "HEAD"].as_slice())
+    ] {
+        let response = server.method(method, path).await;
+        response.assert_status(StatusCode::METHOD_NOT_ALLOWED);
+
+        let allow = response.header(header::ALLOW);
+        let mut allow: Vec<&str> = allow.to_str().unwrap().split(',').map(str::trim).collect();
+        allow.sort_unstable();
+        assert_eq!(allow, allowed, "Allow header of {path}");
+    }
+}
+
+#[tokio::test]
+async fn large_statistics_are_gzipped_when_accepted() {
+    let mut config = common::config();
+    config.compress_responses = true;
+
+    let database = database().await;
+    let (server, _statistics_writer) = common::app_with_config(&database, config).await;
+    let link_id = create_link(&server, "https://example.com/landing").await;
+
+    sqlx::query(
+        r#"
+            insert into link_statistics(link_id, referer, user_agent, ip_address, clicked_at)
+            select $1, 'https://referer-' || n || '.example/', 'test', '127.0.0.1', now()
+            from generate_series(1, 200) as n
+        "#
+    )
+    .bind(&link_id)
+    .execute(&database.pool)
+    .await
+    .unwrap();
+
+    let response = server
+        .get(&format!("/{link_id}/statistics"))
+        .add_header("x-api-key", API_KEY)
+        .add_header(header::ACCEPT_ENCODING, "gzip")
+        .await;
+    response.assert_status(StatusCode::OK);
+    response.assert_header(header::CONTENT_ENCODING, "gzip");
+
+    let response = server
+        .get(&format!("/{link_id}/statistics"))
+        .add_header("x-api-key", API_KEY)
+        .await;
+    response.assert_status(StatusCode::OK);
+    assert!(response.maybe_header(header::CONTENT_ENCODING).is_none());
+}
+
+#[tokio::test]
+async fn preserve_query_carries_the_query_to_the_target() {
+    let mut config = common::config();
+    config.preserve_query = true;
+
+    let database = database().await;
+    let (server, _statistics_writer) = common::app_with_config(&database, config).await;
+
+    for (target_url, expected) in [
+        ("https://example.com/landing", "https://example.com/landing?utm_source=news&b=x+y"),
+        (
+            "https://example.com/landing?ref=short",
+            "https://example.com/landing?ref=short&utm_source=news&b=x+y"
+        )
+    ] {
+        let link_id = create_link(&server, target_url).await;
+
+        let response = server
+            .get(&format!("/{link_id}?utm_source=news&notrack=1&b=x%20y"))
+            .await;
+        response.assert_status(StatusCode::TEMPORARY_REDIRECT);
+        response.assert_header(header::LOCATION, expected);
+    }
+}
+
+#[tokio::test]
+async fn redirect_to_a_target_that_is_no_header_value_fails() {
+    let database = database().await;
+    let (server, _statistics_writer) = app(&database).await;
+
+    // The API refuses such targets, rows written by other means may still hold one.
+    sqlx::query("insert into links(id, target_url) values ('newline', E'https://example.com/a\\nb')")
+        .execute(&database.pool)
+        .await
+        .unwrap();
+
+    server.get("/newline").await.assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[tokio::test]
+async fn delete_links_by_prefix_takes_wildcards_literally() {
+    let database = database().await;
+    let (server, _statistics_writer) = app(&database).await;
+
+    for custom_id in ["camp_a", "campXa"] {
+        server
+            .post("/create")
+            .add_header("x-api-key", API_KEY)
+            .json(&json!({ "targetUrl": "https://example.com/landing", "customId": custom_id }))
+            .await
+            .assert_status(StatusCode::CREATED);
+    }
+
+    let response = server
+        .delete("/links?prefix=camp_")
+        .add_header("x-api-key", API_KEY)
+        .await;
+    response.assert_status(StatusCode::OK);
+    assert_eq!(response.json::<Value>()["deleted"], 1);
+
+    server.get("/camp_a").await.assert_status(StatusCode::NOT_FOUND);
+    server.get("/campXa").await.assert_status(StatusCode::TEMPORARY_REDIRECT);
+}
+
+#[tokio::test]
+async fn patch_link_clears_fields_set_to_null() {
+    let database = database().await;
+    let (server, _statistics_writer) = app(&database).await;
+
+    let response = server
+        .post("/create")
+        .add_header("x-api-key", API_KEY)
+        .json(&json!({
+            "targetUrl": "https://example.com/landing",
+            "expiresAt": (chrono::Utc::now() + chrono::Duration::days(1)).to_rfc3339(),
+            "maxClicks": 10,
+            "cacheControl": "no-store",
+            "referrerPolicy": "no-referrer",
+            "campaignId": "spring"
+        }))
+        .await;
+    response.assert_status(StatusCode::CREATED);
+    let link_id = response.json::<Value>()["id"].as_str().unwrap().to_owned();
+
+    // A missing field keeps its value.
+    let response = server
+        .patch(&format!("/links/{link_id}"))
+        .add_header("x-api-key", API_KEY)
+        .json(&json!({ "permanent": true }))
+        .await;
+    response.assert_status(StatusCode::OK);
+    let link = response.json::<Value>();
+    assert_eq!(link["maxClicks"], 10);
+    assert_eq!(link["campaignId"], "spring");
+
+    let response = server
+        .patch(&format!("/links/{link_id}"))
+        .add_header("x-api-key", API_KEY)
+        .json(&json!({
+            "expiresAt": null,
+            "maxClicks": null,
+            "cacheControl": null,
+            "referrerPolicy": null,
+            "campaignId": null
+        }))
+        .await;
+    response.assert_status(StatusCode::OK);
+
+    let link = response.json::<Value>();
+    for field in ["expiresAt", "maxClicks", "cacheControl", "referrerPolicy", "campaignId"] {
+        assert!(link[field].is_null(), "{field} is {}", link[field]);
+    }
+    assert_eq!(link["permanent"], true);
+}
+
+#[tokio::test]
+async fn forwarded_path_on_a_link_without_forward_path_goes_to_the_not_found_page() {
+    let mut config = common::config();
+    config.not_found_redirect = Some("https://example.com/not-found".to_owned());
+
+    let database = database().await;
+    let (server, _statistics_writer) = common::app_with_config(&database, config).await;
+    let link_id = create_link(&server, "https://example.com/landing").await;
+
+    for path in ["/missing".to_owned(), format!("/{link_id}/more/path")] {
+        let response = server.get(&path).await;
+        response.assert_status(StatusCode::FOUND);
+        response.assert_header(header::LOCATION, "https://example.com/not-found");
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+
+use axum_test::TestServer;
+use shortner::{config::Config, statistics::StatisticsWriter};
+use sqlx::{Connection, Executor, PgConnection, PgPool};
+use testcontainers_modules::postgres::Postgres;
+use testcontainers_modules::testcontainers::{runners::AsyncRunner, ContainerAsync};
+
+pub const API_KEY: &str = "test-key";
+
+/// A migrated database of one test, in a Postgres container removed once
+/// this is dropped. With `TEST_DATABASE_URL` set, a fresh database on that
+/// server is used instead, for machines without Docker.
+pub struct Database {
+    pub pool: PgPool,
+    _container: Option<ContainerAsync<Postgres>>
+}
+
+pub async fn database() -> Database {
+    let (database_url, container) = match std::env::var("TEST_DATABASE_URL") {
+        Ok(server_url) => (create_database(&server_url).await, None),
+        Err(_) => {
+            let container = Postgres::default()
+                .start()
+                .await
+                .expect("Could not start the Postgres container");
+
+            let database_url = format!(
+                "postgres://postgres:postgres@{}:{}/postgres",
+                container.get_host().await.expect("Container has a host"),
+                container.get_host_port_ipv4(5432).await.expect("Container exposes Postgres")
+            );
+
+            (database_url, Some(container))
+        }
+    };
+
+    let pool = PgPool::connect(&database_url).await.expect("Could not connect to Postgres");
+
+    sqlx::migrate!().run(&pool).await.expect("Could not run the migrations");
+
+    Database { pool, _container: container }
+}
+
+async fn create_database(server_url: &str) -> String {
+    let mut connection = PgConnection::connect(server_url)
+        .await
+        .expect("Could not connect to TEST_DATABASE_URL");
+
+    let name = format!("shortner_test_{:016x}", rand::random::<u64>());
+
+    connection
+        .execute(format!("create database {name}").as_str())
+        .await
+        .expect("Could not create the test database");
+
+    let mut database_url = url::Url::parse(server_url).expect("TEST_DATABASE_URL is a url");
+    database_url.set_path(&name);
+
+    database_url.into()
+}
+
+/// The configuration from the environment, accepting `API_KEY`.
+pub fn config() -> Config {
+    let mut config = Config::from_env();
+    config.api_keys = HashSet::from([API_KEY.to_owned()]);
+
+    config
+}
+
+/// The service as `main` builds it, minus `/metrics`, served on a local port
+/// so handlers see the connection's address like in production.
+pub async fn app(database: &Database) -> (TestServer, StatisticsWriter) {
+    app_with_config(database, config()).await
+}
+
+pub async fn app_with_config(database: &Database, config: Config) -> (TestServer, StatisticsWriter) {
+    let (app_state, statistics_writer) = shortner::build_state(database.pool.clone(), config)
+        .await
+        .expect("Could not build the app state");
+
+    let app = shortner::app(shortner::routes(&app_state), app_state);
+
+    let server = TestServer::builder()
+        .http_transport()
+        .build(app.into_make_service_with_connect_info::<SocketAddr>())
+        .expect("Could not start the test server");
+
+    (server, statistics_writer)
+}